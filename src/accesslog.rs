@@ -0,0 +1,91 @@
+// A simple API to ping telegram bots and returns if it's online or not.
+// Copyright (C) 2023  Awiteb <awitb@hotmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::time::Instant;
+
+use salvo::prelude::*;
+
+use crate::api::{AuthOutcome, PingAlive};
+
+/// The access log hoop, logs each request with its response status and duration
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct AccessLog {
+    /// The log level of the access log, `off` to disable it
+    level: log::LevelFilter,
+}
+
+impl AccessLog {
+    /// Create the access log from `TELEPINGBOT_ACCESS_LOG_LEVEL` environment variable,
+    /// the default level is `info`
+    pub(crate) fn from_env() -> Result<Self, String> {
+        let level = match std::env::var("TELEPINGBOT_ACCESS_LOG_LEVEL") {
+            Ok(level) if !level.is_empty() => level.parse().map_err(|_| {
+                format!(
+                    "Invalid value for `TELEPINGBOT_ACCESS_LOG_LEVEL` must be one of `off`, \
+                     `error`, `warn`, `info`, `debug` or `trace`, found `{level}`"
+                )
+            })?,
+            _ => log::LevelFilter::Info,
+        };
+        Ok(Self { level })
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler for AccessLog {
+    async fn handle(
+        &self,
+        req: &mut Request,
+        depot: &mut Depot,
+        res: &mut Response,
+        ctrl: &mut FlowCtrl,
+    ) {
+        let started_in = Instant::now();
+        ctrl.call_next(req, depot, res).await;
+        let Some(level) = self.level.to_level() else {
+            return;
+        };
+
+        let duration_ms = started_in.elapsed().as_millis() as u64;
+        let status = res.status_code.unwrap_or(StatusCode::OK).as_u16();
+        let bot = req
+            .param::<String>("bot_username")
+            .map(|username| format!("@{}", username.to_lowercase()))
+            .or_else(|| req.param::<String>("telegram_id"));
+        let auth = depot.obtain::<AuthOutcome>().ok().map(|auth| auth.0);
+        let alive = depot.obtain::<PingAlive>().ok().and_then(|alive| alive.0);
+        crate::logger::with_fields(
+            &[
+                ("method", req.method().as_str().into()),
+                ("path", req.uri().path().into()),
+                ("bot", bot.into()),
+                ("auth", auth.into()),
+                ("alive", alive.into()),
+                ("status", status.into()),
+                ("duration_ms", duration_ms.into()),
+            ],
+            || {
+                log::log!(
+                    target: "telepingbot::access",
+                    level,
+                    "{} {} {status} {duration_ms}ms",
+                    req.method(),
+                    req.uri().path()
+                )
+            },
+        );
+    }
+}
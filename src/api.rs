@@ -14,100 +14,2345 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use std::sync::Arc;
+use std::{
+    collections::{hash_map::Entry, HashMap, HashSet},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::{Duration, Instant},
+};
 
-use salvo::{catcher::Catcher, http::HeaderValue, hyper::header, logging::Logger, prelude::*};
+use chrono::{DateTime, TimeZone, Utc};
+use lazy_static::lazy_static;
+use salvo::{
+    catcher::Catcher,
+    http::{header::HeaderName, HeaderValue, ParseError},
+    hyper::header,
+    prelude::*,
+    websocket::{Message, WebSocketUpgrade},
+};
+use subtle::{Choice, ConstantTimeEq};
+use tokio::sync::{broadcast, Semaphore, SemaphorePermit};
+use tokio_util::sync::CancellationToken;
 
-use crate::PingList;
+#[cfg(feature = "history")]
+use crate::history::History;
+use crate::{
+    accesslog::AccessLog,
+    cors::Cors,
+    errors::Error,
+    ipallow::IpAllowlist,
+    metrics::{Metrics, PingResult},
+    pinger::{passive_outcome, BotPinger, PingOutcome},
+    ratelimit::RateLimiter,
+    PingList, PingStatus,
+};
+
+lazy_static! {
+    /// The allowed characters of a telegram username
+    static ref USERNAME_REGEX: regex::Regex = regex::Regex::new("^[a-zA-Z][a-zA-Z0-9_]*$").unwrap();
+}
+
+/// The time to wait the telegram client in the health check, in seconds
+const HEALTH_CHECK_TIMEOUT: u64 = 3;
+
+/// The interval between the readiness checks while warming up, in seconds
+const WARM_UP_CHECK_INTERVAL: u64 = 1;
+
+/// The header of the request id
+const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+/// The default window of the uptime
+#[cfg(feature = "history")]
+const DEFAULT_UPTIME_WINDOW: &str = "24h";
+
+/// The max events kept for the slow WebSocket subscribers
+const EVENTS_CAPACITY: usize = 64;
+
+/// The exit code when the telegram session is revoked, with `TELEPINGBOT_EXIT_ON_REVOKED`
+pub(crate) const SESSION_REVOKED_EXIT_CODE: i32 = 3;
+
+/// The max length of the request id sent by the client
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// The default command to send to the bot
+const DEFAULT_COMMAND: &str = "/start";
+/// The prefix of the inline query probe in the bots list, e.g. `inline:query`
+const INLINE_PROBE_PREFIX: &str = "inline:";
+/// The max length of the start payload, limited by telegram
+const MAX_START_PAYLOAD_LEN: usize = 64;
+/// The identity of the requests authorized by their client certificate, instead of a token
+/// digest. The digests are hex, so they can't collide with it
+const CLIENT_CERT_IDENTITY: &str = "client-cert";
+/// The passive probe in the bots list, the bot is not sent anything
+const PASSIVE_PROBE: &str = "passive";
+/// The monitor interval of the low priority bots, as a multiple of the monitor interval
+const LOW_PRIORITY_FACTOR: u32 = 4;
+
+/// The id of the request, injected by the `add_request_id` hoop
+#[derive(Debug, Clone)]
+pub(crate) struct RequestId(pub String);
+
+/// The authentication result of the request, e.g. `AUTHORIZED` or `AUTH_FORBIDDEN`
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct AuthOutcome(pub &'static str);
+
+/// Whether the pinged bot is alive, `None` if the bot is not pinged
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct PingAlive(pub Option<bool>);
+
+/// The prefix of all the routes without the slashes, empty if the routes are in the root
+#[derive(Debug, Clone)]
+pub(crate) struct BasePath(pub String);
+
+/// How the bot is pinged
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Probe {
+    /// Send the command to the bot and wait its message, e.g. `/start`
+    Command(String),
+    /// Send the inline query to the bot and wait its results
+    Inline(String),
+    /// Send nothing, the bot is alive if it sent a message in the passive window
+    Passive,
+}
+
+/// The bot priority, the higher priority bots are pinged first
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Priority {
+    High,
+    #[default]
+    Normal,
+    /// Monitored less often, every [`LOW_PRIORITY_FACTOR`] monitor intervals
+    Low,
+}
+
+/// The options before the probe in a `bots.txt` line, e.g. `chat:-1001234567890 priority:high`
+#[derive(Debug, Default)]
+pub(crate) struct ProbeOptions<'a> {
+    pub chat: Option<&'a str>,
+    pub priority: Option<&'a str>,
+    pub interval: Option<&'a str>,
+    pub timeout: Option<&'a str>,
+    pub expect: Option<&'a str>,
+    pub cooldown: Option<&'a str>,
+    pub start: Option<&'a str>,
+}
+
+/// An authorized bot with its ping configuration
+#[derive(Debug, Clone)]
+pub(crate) struct BotConfig {
+    /// Clean text bot username
+    pub username: String,
+    /// How the bot is pinged
+    pub probe: Probe,
+    /// The group or channel to send the command into, the bot private chat if `None`
+    pub chat: Option<i64>,
+    pub priority: Priority,
+    /// The monitor interval of the bot in seconds, `None` for the default of its priority
+    pub interval: Option<u64>,
+    /// The time to wait the bot response in seconds, `None` for the default response timeout
+    pub timeout: Option<u64>,
+    /// The pattern the bot response must match, any message is a response if it's `None`
+    pub expect: Option<regex::Regex>,
+    /// The min time between the bot pings in seconds, `None` for the default cooldown
+    pub cooldown: Option<u64>,
+    /// The deep-link parameter appended to the default command, e.g. `/start payload`
+    pub start: Option<String>,
+}
+
+/// The authorized bots and tokens, can be reloaded at runtime
+#[derive(Debug)]
+pub(crate) struct AccessLists {
+    /// The authorized bots
+    pub bots: Vec<BotConfig>,
+    /// Sha256 tokens with their bots scope, `None` scope means all the bots
+    pub tokens: HashMap<String, Option<HashSet<String>>>,
+}
+
+/// The sha256 digest of the request token, injected by the [`auth`] hoop
+#[derive(Debug, Clone)]
+struct TokenDigest(String);
+
+/// The uptime of a bot over a time window
+#[cfg(feature = "history")]
+#[derive(Debug, serde::Serialize)]
+struct UptimeSchema {
+    /// The bot username, with `@`
+    bot: String,
+    window: String,
+    /// The fraction of the alive pings, `None` if there is no pings in the window
+    uptime: Option<f64>,
+    samples: u64,
+}
+
+/// A bot status change detected by the monitor, streamed to the WebSocket subscribers
+#[derive(Debug, Clone, serde::Serialize)]
+pub(crate) struct StatusEvent {
+    /// The bot username, with `@`
+    pub bot: String,
+    pub alive: bool,
+    pub latency_ms: Option<u64>,
+    /// When the bot is pinged, in RFC 3339
+    pub ts: String,
+}
+
+/// The last monitor ping result of a bot
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct BotStatus {
+    pub alive: bool,
+    /// The bot response latency in milliseconds
+    pub latency_ms: Option<u64>,
+    /// When the bot is pinged
+    pub checked_at: DateTime<Utc>,
+    /// The last time the bot response, `None` if it never response
+    pub last_seen: Option<DateTime<Utc>>,
+}
 
 #[derive(Debug)]
 pub(crate) struct AppState {
-    /// Clean text bot usernames
-    pub bots: Vec<String>,
-    /// Sha256 tokens
-    pub tokens: Vec<String>,
-    /// The telegram clinet
-    tg_client: grammers_client::Client,
+    /// The authorized bots and tokens
+    pub access: RwLock<AccessLists>,
+    /// The bots pinger, the real telegram accounts or a mock
+    pub pinger: Arc<dyn BotPinger>,
+    /// The time to wait the bot response, in seconds
+    pub response_timeout: u64,
+    /// The time to wait the whole ping, in seconds
+    pub ping_timeout: u64,
+    /// The prometheus metrics
+    pub metrics: Arc<Metrics>,
+    /// The last monitor ping result of each bot, by its clean username
+    pub status_cache: RwLock<HashMap<String, BotStatus>>,
+    /// The bots status changes, sent by the monitor to the WebSocket subscribers
+    pub events: broadcast::Sender<StatusEvent>,
+    /// Cancelled to shut down the API gracefully, by Ctrl-C or `POST /shutdown`
+    pub shutdown: CancellationToken,
+    /// Sign out from telegram after the shutdown, requested by `POST /shutdown?sign_out=true`
+    pub sign_out: AtomicBool,
+    /// The rate limiter of the tokens, `None` if there is no limit
+    rate_limiter: Option<RateLimiter>,
+    /// Response with `200` instead of `404` when the bot doesn't response
+    strict_http: bool,
+    /// The clean usernames of the resolved bots by their telegram id
+    bot_ids: RwLock<HashMap<u64, String>>,
+    /// The ping attempts before reporting that the bot doesn't response
+    ping_attempts: u32,
+    /// The time to wait between the ping attempts
+    retry_delay: Duration,
+    /// Whether the telegram session is revoked, the pings can't be sent until it's signed in again
+    degraded: AtomicBool,
+    /// Whether the startup grace period is over and the telegram accounts are ready
+    ready: AtomicBool,
+    /// The number of the running telegram updates handlers
+    running_handlers: AtomicUsize,
+    /// The number of the last pings that failed with an account error
+    account_failures: AtomicUsize,
+    /// The account failures to report the telegram client as not usable
+    unusable_after: usize,
+    /// Exit with [`SESSION_REVOKED_EXIT_CODE`] when the telegram session is revoked
+    exit_on_revoked: bool,
+    /// The sha256 digest of the admin token, the admin endpoints are disabled without it
+    admin_token: Option<String>,
+    /// The tokens files to persist the added and revoked tokens, the added tokens are appended
+    /// to the first one. Empty if they can't be persisted
+    tokens_files: Vec<PathBuf>,
+    /// Include the error summary in the ping errors responses
+    error_detail: bool,
+    /// Reject the plaintext requests, that are not forwarded over HTTPS
+    require_https: bool,
+    /// Authorize the requests without a token by their verified client certificate
+    client_cert_auth: bool,
+    /// The hint of the root path banner, `None` for the default hint
+    banner_hint: Option<String>,
+    /// The allowed client IPs, `None` if all the IPs are allowed
+    ip_allowlist: Option<IpAllowlist>,
+    /// Limit the concurrent pings, `None` if there is no limit
+    concurrency: Option<Semaphore>,
+    /// The max pings waiting their turn, the extra pings are rejected. `None` if there is no
+    /// limit
+    max_queue: Option<usize>,
+    /// The number of the pings waiting their turn
+    queued: AtomicUsize,
+    /// The maximum bots of a batch ping
+    batch_max_bots: usize,
+    /// The maximum body size of a batch ping, in bytes
+    batch_max_body: usize,
+    /// The command to send to the started bots instead of their command, `None` to always
+    /// send their command
+    followup_command: Option<String>,
+    /// Ping all the bots passively, see [`Probe::Passive`]
+    passive: bool,
+    /// The bot is alive if it sent a message in this window, for the passive pings, in seconds
+    passive_window: u64,
+    /// The bots that sent a message in this window are alive without pinging them, in seconds,
+    /// `0` to always ping them
+    seen_within: u64,
+    /// The clean usernames of the bots that received their command in this session
+    started_bots: RwLock<HashSet<String>>,
+    /// The default min time between the pings of each bot in seconds, `0` for no cooldown
+    cooldown: u64,
+    /// The last ping of the bots in their cooldown, by their clean usernames
+    last_probes: Mutex<HashMap<String, LastProbe>>,
+    /// The ping history database, `None` if `TELEPINGBOT_DB_PATH` is not set
+    #[cfg(feature = "history")]
+    history: Option<History>,
 }
 
 #[derive(serde::Serialize)]
 struct MessageSchema<'a> {
     message: &'a str,
+    /// Whether the request is successful, not whether the bot is alive
     status: bool,
+    /// Whether the bot is alive, `null` if the bot is not pinged (e.g. an error)
+    alive: Option<bool>,
+    /// The bot response latency in milliseconds, `null` if there is no response
+    latency_ms: Option<u64>,
+    /// The seconds to wait before retrying, when telegram limits us
+    #[serde(skip_serializing_if = "Option::is_none")]
+    retry_after_seconds: Option<u32>,
+    /// Machine-readable error code, e.g. `AUTH_FORBIDDEN`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error_code: Option<&'static str>,
+    /// When the bot is pinged, only in the cached results
+    #[serde(skip_serializing_if = "Option::is_none")]
+    checked_at: Option<String>,
+    /// The bot telegram id, only when the bot is pinged and alive
+    #[serde(skip_serializing_if = "Option::is_none")]
+    telegram_id: Option<u64>,
+    /// The ping error summary, only with `TELEPINGBOT_ERROR_DETAIL`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error_detail: Option<String>,
     #[serde(skip)]
     status_code: StatusCode,
 }
 
+#[derive(serde::Serialize)]
+struct HealthSchema {
+    status: bool,
+    /// Whether the telegram client is connected and authorized
+    telegram_authorized: bool,
+    /// Whether the telegram session is revoked while running
+    degraded: bool,
+    /// Whether the startup grace period is over and the API can ping the bots
+    ready: bool,
+    /// Whether the last pings are not all failed with account errors, e.g. the account is banned
+    usable: bool,
+}
+
+#[derive(serde::Serialize)]
+struct VersionSchema {
+    version: &'static str,
+    /// The git commit of the build, `unknown` if it's not built from a git repository
+    git_sha: &'static str,
+    /// When the API is built, in RFC 3339
+    build_time: &'static str,
+}
+
+/// A bot status in the status file, the times are in RFC 3339
+#[derive(serde::Serialize, serde::Deserialize)]
+struct StatusFileSchema {
+    alive: bool,
+    latency_ms: Option<u64>,
+    checked_at: String,
+    last_seen: Option<String>,
+}
+
+#[derive(serde::Serialize)]
+struct BannerSchema {
+    /// The service name
+    name: &'static str,
+    version: &'static str,
+    /// How to use the API
+    hint: String,
+}
+
+/// The response body format of the ping routes, with the `format` query
+#[derive(Debug, Clone, Copy)]
+enum PingFormat {
+    /// The [`MessageSchema`]
+    Default,
+    /// The [`CompatMessageSchema`], for the monitoring systems that expect `ok`
+    Compat,
+}
+
+/// The simplified ping result, with `?format=compat`
+#[derive(serde::Serialize)]
+struct CompatMessageSchema<'a> {
+    /// Whether the bot is alive
+    ok: bool,
+    detail: &'a str,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct NewTokenSchema {
+    token: String,
+    /// The bots the token is allowed to ping, all the bots if it's empty
+    #[serde(default)]
+    bots: Vec<String>,
+    /// Append the token to the tokens file, so it's kept after the restart
+    #[serde(default)]
+    persist: bool,
+}
+
+#[derive(serde::Serialize)]
+struct TokenSchema {
+    /// The token sha256 digest, to revoke it
+    id: String,
+    /// The bots the token is allowed to ping, `null` for all the bots
+    bots: Option<Vec<String>>,
+}
+
+#[derive(serde::Serialize)]
+struct PingedBotSchema {
+    telegram_id: u64,
+    /// When the bot is pinged, in RFC 3339
+    ping_in: String,
+    is_response: bool,
+    /// The milliseconds since the bot is pinged
+    age_ms: i64,
+    /// The ping status, `alive`, `no_response` or `pending` if the bot may still response
+    status: &'static str,
+}
+
+#[derive(serde::Serialize)]
+struct BotSchema {
+    /// The bot username, with `@`
+    username: String,
+    /// The last monitor result, only if the monitor is enabled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    alive: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    checked_at: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_seen: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BatchPingSchema {
+    /// The bot usernames to ping
+    bots: Vec<String>,
+}
+
+#[derive(serde::Serialize)]
+struct BatchResultSchema<'a> {
+    /// The bot username
+    bot: String,
+    /// Whether the token is allowed to ping the bot
+    authorized: bool,
+    /// The status code of the bot result, as if it's pinged alone
+    status_code: u16,
+    #[serde(flatten)]
+    result: MessageSchema<'a>,
+}
+
+impl std::str::FromStr for Priority {
+    type Err = ();
+
+    fn from_str(priority: &str) -> Result<Self, Self::Err> {
+        match priority.to_lowercase().as_str() {
+            "high" => Ok(Self::High),
+            "normal" => Ok(Self::Normal),
+            "low" => Ok(Self::Low),
+            _ => Err(()),
+        }
+    }
+}
+
+impl BotConfig {
+    /// Create new [`BotConfig`] from a `bots.txt` line, the line is the bot username
+    /// followed by the command to send to it, e.g. `@my_bot /health arg1`, or by the inline
+    /// query to send to it, e.g. `@my_bot inline:query`. Without a probe, `/start` is sent.
+    /// The probe can be preceded by the options, e.g. `@my_bot chat:-1001234567890 /health`
+    /// to send the command into the group, `priority:high` and `interval:30` (seconds) for
+    /// the monitor, `timeout:10` (seconds) to wait the bot response, `expect:<regex>` to
+    /// match the response text, `cooldown:60` (seconds) between the pings and `start:<payload>`
+    /// to send `/start <payload>`. The invalid options are ignored
+    pub(crate) fn parse(line: &str) -> Self {
+        let line = line.trim();
+        let (username, probe) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let (options, probe) = split_options(probe);
+        Self {
+            username: clean_username(username),
+            probe: if let Some(query) = probe.strip_prefix(INLINE_PROBE_PREFIX) {
+                Probe::Inline(query.trim().to_owned())
+            } else if probe == PASSIVE_PROBE {
+                Probe::Passive
+            } else if probe.is_empty() {
+                Probe::Command(DEFAULT_COMMAND.to_owned())
+            } else {
+                Probe::Command(probe.to_owned())
+            },
+            chat: options.chat.and_then(|chat| chat.parse().ok()),
+            priority: options
+                .priority
+                .and_then(|priority| priority.parse().ok())
+                .unwrap_or_default(),
+            interval: options
+                .interval
+                .and_then(|interval| interval.parse().ok())
+                .filter(|&interval| interval > 0),
+            timeout: options
+                .timeout
+                .and_then(|timeout| timeout.parse().ok())
+                .filter(|&timeout| timeout > 0),
+            expect: options
+                .expect
+                .and_then(|expect| regex::Regex::new(expect).ok()),
+            cooldown: options
+                .cooldown
+                .and_then(|cooldown| cooldown.parse().ok())
+                .filter(|&cooldown| cooldown > 0),
+            start: options
+                .start
+                .filter(|start| is_start_payload(start))
+                .map(str::to_owned),
+        }
+    }
+
+    /// Returns the command to send to the bot, the start payload is appended to the default
+    /// command only, e.g. `/start payload`
+    pub(crate) fn command<'a>(&self, command: &'a str) -> std::borrow::Cow<'a, str> {
+        match &self.start {
+            Some(payload) if command == DEFAULT_COMMAND => format!("{command} {payload}").into(),
+            _ => command.into(),
+        }
+    }
+
+    /// Returns the monitor interval of the bot, its own interval or the default interval of
+    /// its priority
+    pub(crate) fn monitor_interval(&self, interval: Duration) -> Duration {
+        match (self.interval, self.priority) {
+            (Some(seconds), _) => Duration::from_secs(seconds),
+            (None, Priority::Low) => interval * LOW_PRIORITY_FACTOR,
+            (None, _) => interval,
+        }
+    }
+}
+
+/// Parse a `tokens.txt` line, returns the token sha256 digest and its bots scope, `None` scope
+/// means all the bots
+fn parse_token_line(line: &str) -> Option<(String, Option<HashSet<String>>)> {
+    let mut parts = line.split_whitespace();
+    let token = parts.next()?;
+    let scope: HashSet<String> = parts.map(clean_username).collect();
+    Some((sha256::digest(token), (!scope.is_empty()).then_some(scope)))
+}
+
+/// Whether the start payload is valid, telegram allows up to 64 `A-Z`, `a-z`, `0-9`, `_` and
+/// `-` characters
+fn is_start_payload(payload: &str) -> bool {
+    !payload.is_empty()
+        && payload.len() <= MAX_START_PAYLOAD_LEN
+        && payload
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/// Split the `key:value` options from the probe, returns the options and the rest of the probe
+pub(crate) fn split_options(probe: &str) -> (ProbeOptions<'_>, &str) {
+    let mut options = ProbeOptions::default();
+    let mut probe = probe.trim();
+    loop {
+        let (word, rest) = probe.split_once(char::is_whitespace).unwrap_or((probe, ""));
+        match word.split_once(':') {
+            Some(("chat", chat)) => options.chat = Some(chat),
+            Some(("priority", priority)) => options.priority = Some(priority),
+            Some(("interval", interval)) => options.interval = Some(interval),
+            Some(("timeout", timeout)) => options.timeout = Some(timeout),
+            Some(("expect", expect)) => options.expect = Some(expect),
+            Some(("cooldown", cooldown)) => options.cooldown = Some(cooldown),
+            Some(("start", start)) => options.start = Some(start),
+            _ => break,
+        }
+        probe = rest.trim_start();
+    }
+    (options, probe)
+}
+
+impl ProbeOptions<'_> {
+    /// Returns the error of the first invalid option
+    pub(crate) fn error(&self) -> Option<String> {
+        let is_positive = |value: &str| matches!(value.parse::<u64>(), Ok(v) if v > 0);
+        match (
+            self.chat,
+            self.priority,
+            self.interval,
+            self.timeout,
+            self.cooldown,
+        ) {
+            (Some(chat), ..) if chat.parse::<i64>().is_err() => {
+                Some(format!("the chat id `{chat}` must be a number"))
+            }
+            (_, Some(priority), ..) if priority.parse::<Priority>().is_err() => Some(format!(
+                "the priority `{priority}` must be `high`, `normal` or `low`"
+            )),
+            (_, _, Some(interval), ..) if !is_positive(interval) => Some(format!(
+                "the interval `{interval}` must be a positive number of seconds"
+            )),
+            (.., Some(timeout), _) if !is_positive(timeout) => Some(format!(
+                "the timeout `{timeout}` must be a positive number of seconds"
+            )),
+            (.., Some(cooldown)) if !is_positive(cooldown) => Some(format!(
+                "the cooldown `{cooldown}` must be a positive number of seconds"
+            )),
+            _ if self.start.is_some_and(|start| !is_start_payload(start)) => Some(format!(
+                "the start payload `{}` must be up to {MAX_START_PAYLOAD_LEN} letters, digits, `_` or `-`",
+                self.start.unwrap_or_default()
+            )),
+            _ => self.expect.and_then(|expect| {
+                regex::Regex::new(expect)
+                    .err()
+                    .map(|err| format!("the expect `{expect}` is invalid regex: {err}"))
+            }),
+        }
+    }
+}
+
+impl AccessLists {
+    /// Create new [`AccessLists`] instance from clean bots and tokens, each token line
+    /// can be followed by the bots it's allowed to ping, e.g. `token @first_bot @second_bot`.
+    /// The duplicated bots are removed, the first one is kept. The scopes of the duplicated
+    /// tokens are merged, a token without bots in any line is allowed to ping all the bots
+    pub(crate) fn new(bots: Vec<String>, tokens: Vec<String>) -> Self {
+        let mut unique_bots: Vec<BotConfig> = Vec::with_capacity(bots.len());
+        for bot in bots.iter().map(|b| BotConfig::parse(b)) {
+            if unique_bots.iter().any(|b| b.username == bot.username) {
+                log::warn!("`@{}` is duplicated in the bots list", bot.username);
+            } else {
+                unique_bots.push(bot);
+            }
+        }
+        let mut unique_tokens: HashMap<String, Option<HashSet<String>>> = HashMap::new();
+        for (digest, scope) in tokens.iter().filter_map(|line| parse_token_line(line)) {
+            match unique_tokens.entry(digest) {
+                Entry::Vacant(entry) => {
+                    entry.insert(scope);
+                }
+                Entry::Occupied(mut entry) => match (entry.get_mut(), scope) {
+                    (Some(old_scope), Some(scope)) => old_scope.extend(scope),
+                    (old_scope, _) => *old_scope = None,
+                },
+            }
+        }
+        Self {
+            bots: unique_bots,
+            tokens: unique_tokens,
+        }
+    }
+
+    /// Returns the token digest if it's authorized, the digest is compared against all
+    /// the tokens in constant time, so the comparison doesn't leak timing information
+    pub(crate) fn authorize_token(&self, token: &str) -> Option<String> {
+        let digest = sha256::digest(token.trim());
+        let authorized: bool = self
+            .tokens
+            .keys()
+            .fold(Choice::from(0), |authorized, t| {
+                authorized | t.as_bytes().ct_eq(digest.as_bytes())
+            })
+            .into();
+        authorized.then_some(digest)
+    }
+
+    /// Add the token with its bots scope, `false` if the token already exists
+    pub(crate) fn add_token(&mut self, line: &str) -> bool {
+        let Some((digest, scope)) = parse_token_line(line) else {
+            return false;
+        };
+        match self.tokens.entry(digest) {
+            Entry::Vacant(entry) => {
+                entry.insert(scope);
+                true
+            }
+            Entry::Occupied(_) => false,
+        }
+    }
+
+    /// Remove the token by its sha256 digest, `false` if there is no such token
+    pub(crate) fn remove_token(&mut self, digest: &str) -> bool {
+        self.tokens.remove(digest).is_some()
+    }
+
+    /// Returns the authorized bot by its clean username
+    pub(crate) fn bot(&self, bot_username: &str) -> Option<&BotConfig> {
+        self.bots.iter().find(|b| b.username == bot_username)
+    }
+
+    /// Check if the bot is in the token scope, the bot username must be clean
+    pub(crate) fn is_in_scope(&self, token_digest: &str, bot_username: &str) -> bool {
+        // The client certificates are allowed to ping all the bots
+        if token_digest == CLIENT_CERT_IDENTITY {
+            return true;
+        }
+        match self.tokens.get(token_digest) {
+            Some(Some(scope)) => scope.contains(bot_username),
+            Some(None) => true,
+            None => false,
+        }
+    }
+}
+
+/// The last ping of a bot in its cooldown
+#[derive(Debug)]
+struct LastProbe {
+    probed_in: Instant,
+    /// The bot cooldown when it's pinged
+    cooldown: Duration,
+    /// `None` while the bot is pinged
+    status: Option<BotStatus>,
+}
+
+/// A started ping in the bot cooldown, the ping is forgotten if the guard is dropped without
+/// finishing it, e.g. the request is cancelled
+#[must_use]
+struct ProbeGuard<'a> {
+    last_probes: &'a Mutex<HashMap<String, LastProbe>>,
+    /// The bot username and when it's pinged, `None` if the bot has no cooldown
+    started: Option<(String, Instant)>,
+}
+
+impl ProbeGuard<'_> {
+    /// Record the result of the ping, the failed pings are forgotten so the next request pings
+    /// the bot
+    fn finish(mut self, result: &crate::Result<PingOutcome>) {
+        let (Some((username, _)), Ok(outcome)) = (self.started.take(), result) else {
+            return;
+        };
+        if let Some(probe) = self.last_probes.lock().unwrap().get_mut(&username) {
+            let checked_at = Utc::now();
+            probe.status = Some(BotStatus {
+                alive: outcome.latency_ms().is_some(),
+                latency_ms: outcome.latency_ms(),
+                checked_at,
+                last_seen: outcome.latency_ms().map(|_| checked_at),
+            });
+        }
+    }
+}
+
+impl Drop for ProbeGuard<'_> {
+    fn drop(&mut self) {
+        let Some((username, probed_in)) = self.started.take() else {
+            return;
+        };
+        let mut last_probes = self.last_probes.lock().unwrap();
+        // Only this ping is forgotten, not a newer one of the same bot
+        if last_probes
+            .get(&username)
+            .is_some_and(|probe| probe.probed_in == probed_in)
+        {
+            last_probes.remove(&username);
+        }
+    }
+}
+
 impl AppState {
-    /// Create new [`AppState`] instance from clean bots and tokens
+    /// Create new [`AppState`] instance from clean bots and tokens, the response and ping
+    /// timeouts and the allowed requests per minute of each token
     pub(crate) fn new(
         bots: Vec<String>,
         tokens: Vec<String>,
-        client: grammers_client::Client,
+        pinger: Arc<dyn BotPinger>,
+        response_timeout: u64,
+        ping_timeout: u64,
+        metrics: Arc<Metrics>,
+        rate_limit: Option<u32>,
     ) -> Self {
         Self {
-            bots: bots
-                .into_iter()
-                .map(|b| b.trim_start_matches('@').trim().to_lowercase())
-                .collect(),
-            tokens: tokens
-                .into_iter()
-                .map(|t| sha256::digest(t.trim()))
-                .collect(),
-            tg_client: client,
+            access: RwLock::new(AccessLists::new(bots, tokens)),
+            pinger,
+            response_timeout,
+            ping_timeout,
+            metrics,
+            status_cache: RwLock::new(HashMap::new()),
+            events: broadcast::channel(EVENTS_CAPACITY).0,
+            shutdown: CancellationToken::new(),
+            sign_out: AtomicBool::new(false),
+            rate_limiter: rate_limit.map(RateLimiter::new),
+            strict_http: false,
+            bot_ids: RwLock::new(HashMap::new()),
+            ping_attempts: 1,
+            retry_delay: Duration::ZERO,
+            degraded: AtomicBool::new(false),
+            ready: AtomicBool::new(false),
+            running_handlers: AtomicUsize::new(0),
+            account_failures: AtomicUsize::new(0),
+            unusable_after: usize::MAX,
+            exit_on_revoked: false,
+            admin_token: None,
+            tokens_files: Vec::new(),
+            error_detail: false,
+            require_https: false,
+            client_cert_auth: false,
+            banner_hint: None,
+            ip_allowlist: None,
+            concurrency: None,
+            max_queue: None,
+            queued: AtomicUsize::new(0),
+            batch_max_bots: usize::MAX,
+            batch_max_body: salvo::http::request::secure_max_size(),
+            followup_command: None,
+            passive: false,
+            passive_window: 0,
+            seen_within: 0,
+            started_bots: RwLock::new(HashSet::new()),
+            cooldown: 0,
+            last_probes: Mutex::new(HashMap::new()),
+            #[cfg(feature = "history")]
+            history: None,
+        }
+    }
+
+    /// Response with `200` and `alive: false` instead of `404` when the bot doesn't response
+    pub(crate) fn strict_http(mut self, strict_http: bool) -> Self {
+        self.strict_http = strict_http;
+        self
+    }
+
+    /// Retry the ping if the bot doesn't response, up to `attempts` pings with `delay` between them
+    pub(crate) fn retries(mut self, attempts: u32, delay: Duration) -> Self {
+        self.ping_attempts = attempts;
+        self.retry_delay = delay;
+        self
+    }
+
+    /// Exit when the telegram session is revoked, so the orchestrator can restart the API
+    pub(crate) fn exit_on_revoked(mut self, exit_on_revoked: bool) -> Self {
+        self.exit_on_revoked = exit_on_revoked;
+        self
+    }
+
+    /// Report the telegram client as not usable after the given pings that failed in a row with
+    /// an account error
+    pub(crate) fn unusable_after(mut self, unusable_after: usize) -> Self {
+        self.unusable_after = unusable_after;
+        self
+    }
+
+    /// Set the tokens files to persist the tokens added and revoked by the admin endpoints, the
+    /// added tokens are appended to the first one
+    pub(crate) fn tokens_files(mut self, tokens_files: Vec<PathBuf>) -> Self {
+        self.tokens_files = tokens_files;
+        self
+    }
+
+    /// Set the admin token of the admin endpoints, the empty token is ignored
+    pub(crate) fn admin_token(mut self, admin_token: Option<String>) -> Self {
+        self.admin_token = admin_token
+            .filter(|token| !token.trim().is_empty())
+            .map(|token| sha256::digest(token.trim()));
+        self
+    }
+
+    /// Record the ping results in the history database
+    #[cfg(feature = "history")]
+    pub(crate) fn history(mut self, history: Option<History>) -> Self {
+        self.history = history;
+        self
+    }
+
+    /// Include the error summary in the ping errors responses, e.g. `USERNAME_NOT_OCCUPIED`
+    pub(crate) fn error_detail(mut self, error_detail: bool) -> Self {
+        self.error_detail = error_detail;
+        self
+    }
+
+    /// Reject the requests that are not over HTTPS, directly or by the proxy `X-Forwarded-Proto`
+    pub(crate) fn require_https(mut self, require_https: bool) -> Self {
+        self.require_https = require_https;
+        self
+    }
+
+    /// Only allow the client IPs in the allowlist
+    pub(crate) fn ip_allowlist(mut self, ip_allowlist: Option<IpAllowlist>) -> Self {
+        self.ip_allowlist = ip_allowlist;
+        self
+    }
+
+    /// Limit the concurrent pings to telegram, the extra pings wait their turn
+    pub(crate) fn max_concurrency(mut self, max_concurrency: Option<usize>) -> Self {
+        self.concurrency = max_concurrency.map(Semaphore::new);
+        self
+    }
+
+    /// Reject the pings when the given pings are already waiting their turn, only with
+    /// [`Self::max_concurrency`]
+    pub(crate) fn max_queue(mut self, max_queue: Option<usize>) -> Self {
+        self.max_queue = max_queue;
+        self
+    }
+
+    /// Wait a turn to ping, returns `None` if there is no concurrency limit. Fails with
+    /// [`Error::Overloaded`] if the max pings are already waiting
+    async fn acquire_permit(&self) -> crate::Result<Option<SemaphorePermit<'_>>> {
+        let Some(concurrency) = &self.concurrency else {
+            return Ok(None);
+        };
+        if let Ok(permit) = concurrency.try_acquire() {
+            return Ok(Some(permit));
+        }
+        let _queued = QueuedPing::new(&self.queued);
+        if matches!(self.max_queue, Some(max_queue) if self.queued.load(Ordering::Relaxed) > max_queue)
+        {
+            return Err(Error::Overloaded(self.response_timeout as u32));
+        }
+        // The semaphore is never closed
+        Ok(Some(concurrency.acquire().await.unwrap()))
+    }
+
+    /// Limit the bots and the body size of the batch pings
+    pub(crate) fn batch_limits(mut self, max_bots: usize, max_body: usize) -> Self {
+        self.batch_max_bots = max_bots;
+        self.batch_max_body = max_body;
+        self
+    }
+
+    /// Send the command (e.g. `/start`) to each bot only once, and the followup command in the
+    /// next pings
+    pub(crate) fn followup_command(mut self, followup_command: Option<String>) -> Self {
+        self.followup_command = followup_command
+            .map(|command| command.trim().to_owned())
+            .filter(|command| !command.is_empty());
+        self
+    }
+
+    /// Ping all the bots passively if `passive` is set, the passive bots are alive if they sent
+    /// a message in the `window` seconds
+    pub(crate) fn passive(mut self, passive: bool, window: u64) -> Self {
+        self.passive = passive;
+        self.passive_window = window;
+        self
+    }
+
+    /// Authorize the requests without a token, the TLS listener must require the client
+    /// certificates
+    pub(crate) fn client_cert_auth(mut self, client_cert_auth: bool) -> Self {
+        self.client_cert_auth = client_cert_auth;
+        self
+    }
+
+    /// Save the status cache to the status file, replaced atomically so a crash doesn't leave
+    /// it corrupted
+    pub(crate) fn save_status(&self, status_file: &Path) -> std::io::Result<()> {
+        let statuses: HashMap<String, StatusFileSchema> = self
+            .status_cache
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(username, status)| {
+                (
+                    username.clone(),
+                    StatusFileSchema {
+                        alive: status.alive,
+                        latency_ms: status.latency_ms,
+                        checked_at: status.checked_at.to_rfc3339(),
+                        last_seen: status.last_seen.map(|seen| seen.to_rfc3339()),
+                    },
+                )
+            })
+            .collect();
+        let temp_file = status_file.with_extension("tmp");
+        std::fs::write(&temp_file, serde_json::to_string(&statuses)?)?;
+        std::fs::rename(temp_file, status_file)
+    }
+
+    /// Load the status cache from the status file, only the authorized bots are loaded. A
+    /// missing or corrupted file is ignored, the cache starts empty
+    pub(crate) fn load_status(&self, status_file: &Path) {
+        let content = match std::fs::read_to_string(status_file) {
+            Ok(content) => content,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return,
+            Err(err) => {
+                log::warn!("Faild to read the status file, starting fresh: {err}");
+                return;
+            }
+        };
+        let statuses: HashMap<String, StatusFileSchema> = match serde_json::from_str(&content) {
+            Ok(statuses) => statuses,
+            Err(err) => {
+                log::warn!("The status file is corrupted, starting fresh: {err}");
+                return;
+            }
+        };
+        let parse_time = |time: &str| {
+            DateTime::parse_from_rfc3339(time)
+                .ok()
+                .map(|time| time.with_timezone(&Utc))
+        };
+        let access = self.access.read().unwrap();
+        let mut status_cache = self.status_cache.write().unwrap();
+        for (username, status) in statuses {
+            let Some(checked_at) = parse_time(&status.checked_at) else {
+                continue;
+            };
+            if access.bot(&username).is_some() {
+                status_cache.insert(
+                    username,
+                    BotStatus {
+                        alive: status.alive,
+                        latency_ms: status.latency_ms,
+                        checked_at,
+                        last_seen: status.last_seen.as_deref().and_then(parse_time),
+                    },
+                );
+            }
+        }
+        log::info!(
+            "Loaded {} bots status from the status file",
+            status_cache.len()
+        );
+    }
+
+    /// Set the hint of the root path banner, the empty hint is ignored
+    pub(crate) fn banner_hint(mut self, banner_hint: Option<String>) -> Self {
+        self.banner_hint = banner_hint
+            .map(|hint| hint.trim().to_owned())
+            .filter(|hint| !hint.is_empty());
+        self
+    }
+
+    /// Set the window of the bots that are alive without pinging them, they sent a message in
+    /// the last `seen_within` seconds. `0` to always ping them
+    pub(crate) fn seen_within(mut self, seen_within: u64) -> Self {
+        self.seen_within = seen_within;
+        self
+    }
+
+    /// Returns the outcome of the bot if it sent a message in the seen-within window, so it's
+    /// not pinged. `None` if the window is disabled or the bot is not seen in it
+    async fn seen_outcome(&self, bot: &BotConfig) -> Option<PingOutcome> {
+        if self.seen_within == 0 {
+            return None;
+        }
+        let resolve = self.pinger.resolve(&bot.username);
+        let telegram_id =
+            tokio::time::timeout(Duration::from_secs(self.ping_timeout_of(bot)), resolve)
+                .await
+                .ok()?
+                .ok()?;
+        match passive_outcome(self.pinger.pings(), telegram_id, self.seen_within) {
+            outcome @ PingOutcome::Alive { .. } => Some(outcome),
+            PingOutcome::NoResponse => None,
+        }
+    }
+
+    /// Set the default min time between the pings of each bot in seconds, `0` for no cooldown
+    pub(crate) fn cooldown(mut self, cooldown: u64) -> Self {
+        self.cooldown = cooldown;
+        self
+    }
+
+    /// Returns the min time between the bot pings, its own cooldown or the default cooldown
+    fn cooldown_of(&self, bot: &BotConfig) -> Duration {
+        Duration::from_secs(bot.cooldown.unwrap_or(self.cooldown))
+    }
+
+    /// Start a ping of the bot if it's not in its cooldown, otherwise returns its last result,
+    /// or the remaining cooldown in seconds if the last ping is not done yet. The expired
+    /// cooldowns are forgotten
+    fn start_probe(
+        &self,
+        bot: &BotConfig,
+    ) -> std::result::Result<ProbeGuard<'_>, std::result::Result<BotStatus, u32>> {
+        let cooldown = self.cooldown_of(bot);
+        if cooldown.is_zero() {
+            return Ok(ProbeGuard {
+                last_probes: &self.last_probes,
+                started: None,
+            });
+        }
+        let mut last_probes = self.last_probes.lock().unwrap();
+        if let Some(probe) = last_probes.get(&bot.username) {
+            // The elapsed time is read once, so the remaining cooldown never underflows
+            let elapsed = probe.probed_in.elapsed();
+            if elapsed < probe.cooldown {
+                return Err(probe.status.ok_or_else(|| {
+                    probe.cooldown.saturating_sub(elapsed).as_secs_f64().ceil() as u32
+                }));
+            }
+        }
+        last_probes.retain(|_, probe| probe.probed_in.elapsed() < probe.cooldown);
+        let probed_in = Instant::now();
+        last_probes.insert(
+            bot.username.clone(),
+            LastProbe {
+                probed_in,
+                cooldown,
+                status: None,
+            },
+        );
+        Ok(ProbeGuard {
+            last_probes: &self.last_probes,
+            started: Some((bot.username.clone(), probed_in)),
+        })
+    }
+
+    /// Returns the bot config of the next ping, the started bots are pinged with the followup
+    /// command if it's set
+    fn probe_config(&self, bot: &BotConfig) -> BotConfig {
+        match (&self.followup_command, &bot.probe) {
+            _ if self.passive => BotConfig {
+                probe: Probe::Passive,
+                ..bot.clone()
+            },
+            (Some(followup), Probe::Command(_))
+                if self.started_bots.read().unwrap().contains(&bot.username) =>
+            {
+                BotConfig {
+                    probe: Probe::Command(followup.clone()),
+                    ..bot.clone()
+                }
+            }
+            _ => bot.clone(),
+        }
+    }
+
+    /// Mark the bot as started, its command is sent
+    fn mark_started(&self, bot_username: &str) {
+        if self.followup_command.is_some()
+            && !self.started_bots.read().unwrap().contains(bot_username)
+        {
+            self.started_bots
+                .write()
+                .unwrap()
+                .insert(bot_username.to_owned());
+        }
+    }
+
+    /// Count a running telegram updates handler
+    pub(crate) fn handler_started(&self) {
+        self.running_handlers.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns `true` if the API is ready to ping the bots
+    pub(crate) fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::Relaxed)
+    }
+
+    /// Wait the startup grace period, then mark the API as ready once the updates handlers are
+    /// running and the telegram accounts are authorized
+    pub(crate) async fn warm_up(&self, grace: Duration, handlers: usize) {
+        tokio::time::sleep(grace).await;
+        loop {
+            let is_authorized = tokio::time::timeout(
+                Duration::from_secs(HEALTH_CHECK_TIMEOUT),
+                self.pinger.is_authorized(),
+            )
+            .await
+            .unwrap_or(false);
+            if is_authorized && self.running_handlers.load(Ordering::Relaxed) >= handlers {
+                break;
+            }
+            tokio::time::sleep(Duration::from_secs(WARM_UP_CHECK_INTERVAL)).await;
+        }
+        self.ready.store(true, Ordering::Relaxed);
+        log::info!("The API is ready to ping the bots");
+    }
+
+    /// Mark the API as degraded because the telegram session is revoked, or exit if
+    /// `exit_on_revoked` is set
+    pub(crate) fn session_revoked(&self, reason: &str) {
+        if !self.degraded.swap(true, Ordering::Relaxed) {
+            log::error!(
+                "The telegram session is revoked ({reason}), sign in again to ping the bots"
+            );
+        }
+        if self.exit_on_revoked {
+            log::error!("Exiting because the telegram session is revoked");
+            std::process::exit(SESSION_REVOKED_EXIT_CODE);
+        }
+    }
+
+    /// Record whether the ping failed with an account error, the other results reset the
+    /// account failures
+    fn record_account_result(&self, account_error: bool) {
+        if account_error {
+            let failures = self.account_failures.fetch_add(1, Ordering::Relaxed) + 1;
+            if failures == self.unusable_after {
+                log::error!(
+                    "The last {failures} pings failed with account errors, the telegram client is not usable"
+                );
+            }
+        } else if self.account_failures.swap(0, Ordering::Relaxed) >= self.unusable_after {
+            log::info!("The telegram client is usable again");
+        }
+    }
+
+    /// Whether the telegram client can ping the bots, not limited or banned
+    pub(crate) fn is_usable(&self) -> bool {
+        self.account_failures.load(Ordering::Relaxed) < self.unusable_after
+    }
+
+    /// Returns the time to wait the bot response, its own timeout or the default response
+    /// timeout, in seconds
+    pub(crate) fn response_timeout_of(&self, bot: &BotConfig) -> u64 {
+        bot.timeout.unwrap_or(self.response_timeout)
+    }
+
+    /// Returns the time to wait the whole ping of the bot, the ping timeout is extended by the
+    /// bot timeout over the default response timeout, in seconds
+    fn ping_timeout_of(&self, bot: &BotConfig) -> u64 {
+        let margin = self.ping_timeout.saturating_sub(self.response_timeout);
+        self.ping_timeout
+            .max(self.response_timeout_of(bot) + margin)
+    }
+
+    /// Returns the longest response timeout of the authorized bots, in seconds
+    pub(crate) fn max_response_timeout(&self) -> u64 {
+        self.access
+            .read()
+            .unwrap()
+            .bots
+            .iter()
+            .map(|bot| self.response_timeout_of(bot))
+            .fold(self.response_timeout, u64::max)
+    }
+
+    /// Returns the clean username of the authorized bot with the telegram id, the authorized
+    /// bots that are not resolved yet are resolved to find it
+    pub(crate) async fn bot_by_id(&self, telegram_id: u64) -> Option<String> {
+        let is_authorized = |username: &str| self.access.read().unwrap().bot(username).is_some();
+        let username = self.bot_ids.read().unwrap().get(&telegram_id).cloned();
+        if let Some(username) = username {
+            return is_authorized(&username).then_some(username);
+        }
+
+        let unresolved: Vec<String> = {
+            let bot_ids = self.bot_ids.read().unwrap();
+            self.access
+                .read()
+                .unwrap()
+                .bots
+                .iter()
+                .filter(|bot| !bot_ids.values().any(|username| username == &bot.username))
+                .map(|bot| bot.username.clone())
+                .collect()
+        };
+        for username in unresolved {
+            match self.pinger.resolve(&username).await {
+                Ok(id) => {
+                    self.bot_ids.write().unwrap().insert(id, username.clone());
+                    if id == telegram_id {
+                        return is_authorized(&username).then_some(username);
+                    }
+                }
+                Err(err) => log::warn!("Faild to resolve `@{username}`: {err}"),
+            }
         }
+        None
+    }
+
+    /// Replace the authorized bots and tokens with the new clean ones
+    pub(crate) fn reload(&self, bots: Vec<String>, tokens: Vec<String>) {
+        *self.access.write().unwrap() = AccessLists::new(bots, tokens);
+    }
+}
+
+impl<'a> MessageSchema<'a> {
+    /// Create new [`Message`] instance with `200 OK` status
+    fn new(message: &'a str) -> Self {
+        Self {
+            message,
+            status: true,
+            alive: None,
+            latency_ms: None,
+            retry_after_seconds: None,
+            error_code: None,
+            checked_at: None,
+            telegram_id: None,
+            error_detail: None,
+            status_code: StatusCode::OK,
+        }
+    }
+
+    /// Set the bot response latency, the bot is alive
+    fn latency(mut self, latency_ms: u64) -> Self {
+        self.alive = Some(true);
+        self.latency_ms = Some(latency_ms);
+        self
+    }
+
+    /// Set the bot telegram id
+    fn telegram_id(mut self, telegram_id: u64) -> Self {
+        self.telegram_id = Some(telegram_id);
+        self
+    }
+
+    /// Set the ping error summary
+    fn error_detail(mut self, error_detail: String) -> Self {
+        self.error_detail = Some(error_detail);
+        self
+    }
+
+    /// Set the seconds to wait before retrying
+    fn retry_after(mut self, seconds: u32) -> Self {
+        self.retry_after_seconds = Some(seconds);
+        self
+    }
+
+    /// Set the machine-readable error code
+    fn error_code(mut self, error_code: &'static str) -> Self {
+        self.error_code = Some(error_code);
+        self
+    }
+
+    /// Set when the bot is pinged, for the cached results
+    fn checked_at(mut self, checked_at: DateTime<Utc>) -> Self {
+        self.checked_at = Some(checked_at.to_rfc3339());
+        self
+    }
+
+    /// Returns the message of a bot that doesn't response, `200` with `alive: false` in the
+    /// strict HTTP mode, otherwise `404`
+    fn no_response(strict_http: bool) -> Self {
+        let msg = Self {
+            alive: Some(false),
+            ..Self::new("No response from the bot")
+        };
+        if strict_http {
+            Self {
+                status: false,
+                ..msg
+            }
+        } else {
+            msg.code(StatusCode::NOT_FOUND)
+        }
+    }
+
+    /// Update the status code and status
+    fn code(mut self, status_code: StatusCode) -> Self {
+        self.status = status_code.is_success();
+        self.status_code = status_code;
+        self
+    }
+}
+
+/// Check if the bot username (without `@`) is a valid telegram bot username, returns the
+/// violated rule if it's not
+pub(crate) fn validate_username(username: &str) -> Result<(), &'static str> {
+    if !(5..=32).contains(&username.len()) {
+        Err("The bot username must be 5 to 32 characters")
+    } else if !USERNAME_REGEX.is_match(username) {
+        Err("The bot username must start with a letter and contain only letters, numbers and `_`")
+    } else if !username.to_lowercase().ends_with("bot") {
+        Err("The bot username must end with `bot`")
+    } else {
+        Ok(())
+    }
+}
+
+/// Returns the bot username without `@` and lowercase
+pub(crate) fn clean_username(username: &str) -> String {
+    username.trim().trim_start_matches('@').to_lowercase()
+}
+
+fn write_json_body(res: &mut Response, json_body: impl serde::Serialize) {
+    res.write_body(serde_json::to_string(&json_body).unwrap())
+        .ok();
+}
+
+/// Ping the bot and returns the result, the username must be clean.
+/// With `cached` the last monitor result is returned if there is one.
+/// Only the authorized bots are recorded in the metrics, to keep the labels bounded
+async fn ping_bot(
+    app_state: &AppState,
+    token: &TokenDigest,
+    bot_username: &str,
+    cached: bool,
+) -> MessageSchema<'static> {
+    let (bot, is_in_scope) = {
+        let access = app_state.access.read().unwrap();
+        (
+            access.bot(bot_username).cloned(),
+            access.is_in_scope(&token.0, bot_username),
+        )
+    };
+
+    if let Err(rule) = validate_username(bot_username) {
+        return MessageSchema::new(rule).code(StatusCode::BAD_REQUEST);
+    }
+    let Some(bot) = bot else {
+        return MessageSchema::new("Is not authorized to check the status of this bot")
+            .code(StatusCode::BAD_REQUEST);
+    };
+    if !is_in_scope {
+        MessageSchema::new("The token is not allowed to check the status of this bot")
+            .code(StatusCode::FORBIDDEN)
+    } else {
+        if cached {
+            let status = app_state
+                .status_cache
+                .read()
+                .unwrap()
+                .get(bot_username)
+                .copied();
+            if let Some(status) = status {
+                return cached_message(status, app_state.strict_http);
+            }
+            log::debug!("`@{bot_username}` is not in the status cache, pinging it");
+        }
+        if !app_state.is_ready() {
+            return MessageSchema::new("The API is warming up, try again later")
+                .code(StatusCode::SERVICE_UNAVAILABLE)
+                .error_code("WARMING_UP");
+        }
+        let probe = match app_state.start_probe(&bot) {
+            Ok(probe) => probe,
+            Err(Ok(status)) => {
+                log::debug!("`@{bot_username}` is in its cooldown, returning its last result");
+                return cached_message(status, app_state.strict_http);
+            }
+            Err(Err(retry_after)) => {
+                return MessageSchema::new("The bot is being pinged, try again later")
+                    .code(StatusCode::TOO_MANY_REQUESTS)
+                    .retry_after(retry_after)
+                    .error_code("COOLDOWN");
+            }
+        };
+        let result = probe_bot(app_state, &bot).await;
+        probe.finish(&result);
+        match result {
+            Ok(PingOutcome::Alive {
+                latency_ms,
+                telegram_id,
+            }) => MessageSchema::new("Alive")
+                .latency(latency_ms)
+                .telegram_id(telegram_id),
+            Ok(PingOutcome::NoResponse) => MessageSchema::no_response(app_state.strict_http),
+            Err(err) if app_state.error_detail => error_message(&err).error_detail(err.detail()),
+            Err(err) => error_message(&err),
+        }
+    }
+}
+
+/// Ping the bot with the pinger and record the result in the metrics
+pub(crate) async fn probe_bot(app_state: &AppState, bot: &BotConfig) -> crate::Result<PingOutcome> {
+    let bot_username = bot.username.as_str();
+    let passive = app_state.passive || bot.probe == Probe::Passive;
+    // The busy bots are alive without pinging them, the idle bots are pinged as usual
+    if !passive {
+        if let Some(outcome) = app_state.seen_outcome(bot).await {
+            log::debug!(
+                "`@{bot_username}` is seen in the last {}s, not pinging it",
+                app_state.seen_within
+            );
+            record_ping(app_state, bot_username, PingResult::Alive, None);
+            return Ok(outcome);
+        }
+    }
+    let mut attempt = 1;
+    let result = loop {
+        let permit = match app_state.acquire_permit().await {
+            Ok(permit) => permit,
+            // There is no telegram work, so it's not recorded
+            Err(err) => {
+                log::warn!("Rejecting the ping of `@{bot_username}`: {err}");
+                return Err(err);
+            }
+        };
+        let config = app_state.probe_config(bot);
+        let (response_timeout, ping_timeout) = (
+            if passive {
+                app_state.passive_window
+            } else {
+                app_state.response_timeout_of(bot)
+            },
+            app_state.ping_timeout_of(bot),
+        );
+        log::debug!(
+            "Pinging `@{bot_username}` with {response_timeout}s response timeout and {ping_timeout}s ping timeout"
+        );
+        let probe = app_state.pinger.ping(&config, response_timeout);
+        // Resolving and sending may hang, so the whole ping has its own timeout
+        let result = tokio::time::timeout(Duration::from_secs(ping_timeout), probe)
+            .await
+            .unwrap_or(Err(Error::Timeout));
+        if result.is_ok() {
+            app_state.mark_started(bot_username);
+        }
+        // Telegram may drop the response, so retry before reporting that the bot doesn't
+        // response. The retries reuse the same pinged bot entry
+        if passive
+            || !matches!(result, Ok(PingOutcome::NoResponse))
+            || attempt >= app_state.ping_attempts
+        {
+            break result;
+        }
+        log::debug!(
+            "No response from `@{bot_username}`, retrying ({attempt}/{})",
+            app_state.ping_attempts
+        );
+        attempt += 1;
+        drop(permit);
+        tokio::time::sleep(app_state.retry_delay).await;
+    };
+
+    app_state.record_account_result(matches!(&result, Err(err) if err.is_account_error()));
+    match result {
+        Ok(outcome) => {
+            match outcome {
+                // The passive pings have no response latency
+                PingOutcome::Alive { latency_ms, .. } => record_ping(
+                    app_state,
+                    bot_username,
+                    PingResult::Alive,
+                    (!passive).then_some(latency_ms),
+                ),
+                PingOutcome::NoResponse => {
+                    record_ping(app_state, bot_username, PingResult::NoResponse, None)
+                }
+            }
+            Ok(outcome)
+        }
+        Err(err) => {
+            match &err {
+                Error::FloodWait(seconds) => {
+                    let (flood_waits, flood_wait_seconds) =
+                        app_state.metrics.record_flood_wait(*seconds);
+                    log::warn!(
+                        "Faild to send to `@{bot_username}`: {err}, {flood_waits} flood waits \
+                         ({flood_wait_seconds} seconds) in total"
+                    )
+                }
+                Error::SessionRevoked(reason) => app_state.session_revoked(reason),
+                _ => log::error!("Faild to send to `@{bot_username}`: {err}"),
+            }
+            record_ping(app_state, bot_username, PingResult::Error, None);
+            Err(err)
+        }
+    }
+}
+
+/// A ping waiting its turn, counted in the queued pings until it's dropped
+struct QueuedPing<'a>(&'a AtomicUsize);
+
+impl<'a> QueuedPing<'a> {
+    fn new(queued: &'a AtomicUsize) -> Self {
+        queued.fetch_add(1, Ordering::Relaxed);
+        Self(queued)
+    }
+}
+
+impl Drop for QueuedPing<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Returns the response message of the monitor result
+fn cached_message(status: BotStatus, strict_http: bool) -> MessageSchema<'static> {
+    let msg = if status.alive {
+        MessageSchema {
+            alive: Some(true),
+            ..MessageSchema::new("Alive")
+        }
+    } else {
+        MessageSchema::no_response(strict_http)
+    };
+    let msg = msg.checked_at(status.checked_at);
+    match status.latency_ms {
+        Some(latency_ms) => msg.latency(latency_ms),
+        None => msg,
+    }
+}
+
+/// Record the ping result in the metrics and the logs
+fn record_ping(
+    app_state: &AppState,
+    bot_username: &str,
+    result: PingResult,
+    latency_ms: Option<u64>,
+) {
+    app_state
+        .metrics
+        .record_ping(bot_username, result, latency_ms);
+    // The errors are not the bot fault, so they are not in the history
+    #[cfg(feature = "history")]
+    if let (Some(history), true) = (&app_state.history, result != PingResult::Error) {
+        if let Err(err) = history.record(
+            bot_username,
+            Utc::now(),
+            result == PingResult::Alive,
+            latency_ms,
+        ) {
+            log::error!("Faild to record the ping of `@{bot_username}` in the history: {err}");
+        }
+    }
+    crate::logger::with_fields(
+        &[
+            ("bot", format!("@{bot_username}").into()),
+            ("result", result.as_str().into()),
+            ("latency_ms", latency_ms.into()),
+        ],
+        || log::info!("Ping result of `@{bot_username}`: {}", result.as_str()),
+    );
+}
+
+/// Returns the response message of the ping error
+fn error_message(err: &Error) -> MessageSchema<'static> {
+    match err {
+        Error::FloodWait(seconds) => {
+            MessageSchema::new("Too many requests to telegram, try again later")
+                .code(StatusCode::TOO_MANY_REQUESTS)
+                .retry_after(*seconds)
+        }
+        Error::Overloaded(seconds) => MessageSchema::new("Too many pings, try again later")
+            .code(StatusCode::SERVICE_UNAVAILABLE)
+            .retry_after(*seconds)
+            .error_code("OVERLOADED"),
+        Error::Resolve(_) => MessageSchema::new("Cant resolve the bot username")
+            .code(StatusCode::INTERNAL_SERVER_ERROR),
+        Error::NotBot(_) => MessageSchema::new("The username is not a bot account")
+            .code(StatusCode::INTERNAL_SERVER_ERROR),
+        Error::ChatNotFound(_) => {
+            MessageSchema::new("Cant find the chat of the bot, the superbot must be a member of it")
+                .code(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+        Error::Timeout => {
+            MessageSchema::new("Timeout while pinging the bot").code(StatusCode::GATEWAY_TIMEOUT)
+        }
+        Error::Blocked(_) => MessageSchema::new("The bot is blocked, can't send to it")
+            .code(StatusCode::FORBIDDEN)
+            .error_code("TELEGRAM_BLOCKED"),
+        Error::Restricted(_) => {
+            MessageSchema::new("Telegram restricted the superbot from sending to the bot")
+                .code(StatusCode::FORBIDDEN)
+                .error_code("TELEGRAM_RESTRICTED")
+        }
+        Error::SessionRevoked(_) => {
+            MessageSchema::new("The telegram session is revoked, can't send to the bot")
+                .code(StatusCode::SERVICE_UNAVAILABLE)
+                .error_code("TELEGRAM_UNAUTHORIZED")
+        }
+        Error::Telegram(_) => {
+            MessageSchema::new("Cant send to the bot").code(StatusCode::BAD_GATEWAY)
+        }
+        _ => MessageSchema::new("Cant send to the bot").code(StatusCode::INTERNAL_SERVER_ERROR),
+    }
+}
+
+#[handler]
+async fn ping(req: &Request, res: &mut Response, depot: &mut Depot) {
+    // The `@` is optional, e.g. `/ping/my_bot` and the encoded `/ping/%40my_bot`
+    let bot_username = req
+        .param::<String>("bot_username")
+        .unwrap()
+        .trim_start_matches('@')
+        .to_lowercase();
+    let app_state = depot.obtain::<Arc<AppState>>().unwrap();
+    let token = depot.obtain::<TokenDigest>().unwrap();
+    let cached = req.query::<bool>("cached").unwrap_or_default();
+    let Some(format) = PingFormat::from_query(req) else {
+        return write_ping_message(res, PingFormat::invalid_message());
+    };
+
+    let msg = ping_bot(app_state, token, &bot_username, cached).await;
+    depot.inject(PingAlive(msg.alive));
+    format.write(res, msg);
+}
+
+#[handler]
+async fn ping_by_id(req: &Request, res: &mut Response, depot: &mut Depot) {
+    let app_state = depot.obtain::<Arc<AppState>>().unwrap();
+    let token = depot.obtain::<TokenDigest>().unwrap();
+    let cached = req.query::<bool>("cached").unwrap_or_default();
+    let Some(format) = PingFormat::from_query(req) else {
+        return write_ping_message(res, PingFormat::invalid_message());
+    };
+
+    let bot_username = match req.param::<u64>("telegram_id") {
+        Some(telegram_id) => app_state.bot_by_id(telegram_id).await,
+        None => None,
+    };
+    let msg = match bot_username {
+        Some(bot_username) => ping_bot(app_state, token, &bot_username, cached).await,
+        None => MessageSchema::new("Is not authorized to check the status of this bot")
+            .code(StatusCode::BAD_REQUEST),
+    };
+    depot.inject(PingAlive(msg.alive));
+    format.write(res, msg);
+}
+
+impl PingFormat {
+    /// Returns the format of the `format` query, `None` if it's invalid
+    fn from_query(req: &Request) -> Option<Self> {
+        match req.query::<String>("format").as_deref() {
+            None | Some("default") => Some(Self::Default),
+            Some("compat") => Some(Self::Compat),
+            Some(_) => None,
+        }
+    }
+
+    /// The response message of an invalid `format` query
+    fn invalid_message() -> MessageSchema<'static> {
+        MessageSchema::new("Invalid `format`, expected `default` or `compat`")
+            .code(StatusCode::BAD_REQUEST)
+    }
+
+    /// Write the ping result in the format, the status code and the headers are the same
+    fn write(self, res: &mut Response, msg: MessageSchema<'_>) {
+        match self {
+            Self::Default => write_ping_message(res, msg),
+            Self::Compat => {
+                write_ping_status(res, &msg);
+                write_json_body(
+                    res,
+                    CompatMessageSchema {
+                        ok: msg.alive == Some(true),
+                        detail: msg.message,
+                    },
+                );
+            }
+        }
+    }
+}
+
+/// Write the ping result with its status code and the `Retry-After` header
+fn write_ping_message(res: &mut Response, msg: MessageSchema<'_>) {
+    write_ping_status(res, &msg);
+    write_json_body(res, msg);
+}
+
+/// Write the status code and the `Retry-After` header of the ping result
+fn write_ping_status(res: &mut Response, msg: &MessageSchema<'_>) {
+    res.status_code(msg.status_code);
+    if let Some(seconds) = msg.retry_after_seconds {
+        res.headers_mut()
+            .insert(header::RETRY_AFTER, HeaderValue::from(seconds));
+    }
+}
+
+#[handler]
+async fn batch_ping(req: &mut Request, res: &mut Response, depot: &mut Depot) {
+    let app_state = depot.obtain::<Arc<AppState>>().unwrap();
+    let token = depot.obtain::<TokenDigest>().unwrap();
+    let cached = req.query::<bool>("cached").unwrap_or_default();
+
+    let too_large = || {
+        log::info!("Too large batch ping body");
+        MessageSchema::new("The body is too large")
+            .code(StatusCode::PAYLOAD_TOO_LARGE)
+            .error_code("BATCH_TOO_LARGE")
+    };
+    // The body is not read if its `Content-Length` is larger than the limit
+    if req
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|length| length.to_str().ok()?.parse::<usize>().ok())
+        .is_some_and(|length| length > app_state.batch_max_body)
+    {
+        return write_ping_message(res, too_large());
+    }
+
+    match req
+        .parse_json_with_max_size::<BatchPingSchema>(app_state.batch_max_body)
+        .await
+    {
+        // The body is larger than the limit while reading it
+        Err(ParseError::Other(err)) if err.is::<http_body_util::LengthLimitError>() => {
+            write_ping_message(res, too_large());
+        }
+        // e.g. a missing `bots` field, an unknown field or a bot that is not a string
+        Err(ParseError::SerdeJson(err)) => {
+            log::info!("Invalid batch ping body: {err}");
+            let message = format!("Invalid body: {err}");
+            let msg = MessageSchema::new(&message).code(StatusCode::BAD_REQUEST);
+            res.status_code(msg.status_code);
+            write_json_body(res, msg);
+        }
+        Ok(body) if body.bots.is_empty() => {
+            log::info!("Empty batch ping body");
+            let msg = MessageSchema::new("Invalid body: `bots` must not be empty")
+                .code(StatusCode::BAD_REQUEST);
+            res.status_code(msg.status_code);
+            write_json_body(res, msg);
+        }
+        Ok(body) if body.bots.len() > app_state.batch_max_bots => {
+            log::info!("Too many bots in the batch ping: {}", body.bots.len());
+            let msg = MessageSchema::new("Too many bots in the batch")
+                .code(StatusCode::BAD_REQUEST)
+                .error_code("BATCH_TOO_MANY_BOTS");
+            res.status_code(msg.status_code);
+            write_json_body(res, msg);
+        }
+        Ok(body) => {
+            let mut bots: Vec<(usize, String)> = body.bots.into_iter().enumerate().collect();
+            // The higher priority bots are pinged first, the results keep the body order
+            {
+                let access = app_state.access.read().unwrap();
+                bots.sort_by_key(|(_, bot)| {
+                    access
+                        .bot(&clean_username(bot))
+                        .map(|bot| bot.priority)
+                        .unwrap_or_default()
+                });
+            }
+            let mut results =
+                futures_util::future::join_all(bots.into_iter().map(|(index, bot)| async move {
+                    let bot_username = clean_username(&bot);
+                    let authorized = {
+                        let access = app_state.access.read().unwrap();
+                        access.bot(&bot_username).is_some()
+                            && access.is_in_scope(&token.0, &bot_username)
+                    };
+                    let result = ping_bot(app_state, token, &bot_username, cached).await;
+                    let result = BatchResultSchema {
+                        bot,
+                        authorized,
+                        status_code: result.status_code.as_u16(),
+                        result,
+                    };
+                    (index, result)
+                }))
+                .await;
+            results.sort_by_key(|(index, _)| *index);
+            // Multi-status if only some bots are authorized, each result has its own status
+            let authorized = results
+                .iter()
+                .filter(|(_, result)| result.authorized)
+                .count();
+            if authorized == 0 {
+                let forbidden = results
+                    .iter()
+                    .any(|(_, result)| result.status_code == StatusCode::FORBIDDEN.as_u16());
+                res.status_code(if forbidden {
+                    StatusCode::FORBIDDEN
+                } else {
+                    StatusCode::BAD_REQUEST
+                });
+            } else if authorized < results.len() {
+                res.status_code(StatusCode::MULTI_STATUS);
+            }
+            write_json_body(
+                res,
+                results
+                    .into_iter()
+                    .map(|(_, result)| result)
+                    .collect::<Vec<_>>(),
+            );
+        }
+        // e.g. a wrong content type or faild to read the body
+        Err(err) => {
+            log::info!("Invalid batch ping body: {err}");
+            let msg = MessageSchema::new("Invalid body, expected `{\"bots\": [\"@username\"]}`")
+                .code(StatusCode::BAD_REQUEST);
+            res.status_code(msg.status_code);
+            write_json_body(res, msg);
+        }
+    }
+}
+
+#[handler]
+async fn list_bots(res: &mut Response, depot: &mut Depot) {
+    let app_state = depot.obtain::<Arc<AppState>>().unwrap();
+    let token = depot.obtain::<TokenDigest>().unwrap();
+
+    let access = app_state.access.read().unwrap();
+    let status_cache = app_state.status_cache.read().unwrap();
+    let bots: Vec<BotSchema> = access
+        .bots
+        .iter()
+        .filter(|bot| access.is_in_scope(&token.0, &bot.username))
+        .map(|bot| {
+            let status = status_cache.get(&bot.username);
+            BotSchema {
+                username: format!("@{}", bot.username),
+                alive: status.map(|s| s.alive),
+                checked_at: status.map(|s| s.checked_at.to_rfc3339()),
+                last_seen: status.and_then(|s| s.last_seen).map(|t| t.to_rfc3339()),
+            }
+        })
+        .collect();
+    write_json_body(res, bots);
+}
+
+#[handler]
+async fn debug_pings(res: &mut Response, depot: &mut Depot) {
+    let app_state = depot.obtain::<Arc<AppState>>().unwrap();
+    // The ping telegram id doesn't tell its bot, so wait the longest timeout
+    let response_timeout = app_state.max_response_timeout();
+    let now = Utc::now().timestamp_millis();
+    let pings: Vec<PingedBotSchema> = app_state
+        .pinger
+        .pings()
+        .pinged
+        .snapshot()
+        .into_iter()
+        .map(|(telegram_id, bot)| PingedBotSchema {
+            telegram_id,
+            ping_in: Utc
+                .timestamp_millis_opt(bot.ping_in)
+                .single()
+                .unwrap_or_default()
+                .to_rfc3339(),
+            is_response: bot.is_response,
+            age_ms: now - bot.ping_in,
+            status: match bot.status(response_timeout) {
+                PingStatus::Alive => "alive",
+                PingStatus::NoResponse => "no_response",
+                PingStatus::Pending => "pending",
+            },
+        })
+        .collect();
+    write_json_body(res, pings);
+}
+
+/// Add a new API token, the admin token is required
+#[handler]
+async fn add_token(req: &mut Request, res: &mut Response, depot: &mut Depot) {
+    let app_state = depot.obtain::<Arc<AppState>>().unwrap();
+    let body = match req.parse_json::<NewTokenSchema>().await {
+        Ok(body) => body,
+        Err(err) => {
+            log::info!("Invalid new token body: {err}");
+            let message = format!("Invalid body: {err}");
+            return write_ping_message(
+                res,
+                MessageSchema::new(&message).code(StatusCode::BAD_REQUEST),
+            );
+        }
+    };
+    let token = body.token.trim();
+    // The `#` starts a comment in the tokens file
+    if token.is_empty() || token.contains(char::is_whitespace) || token.starts_with('#') {
+        return write_ping_message(
+            res,
+            MessageSchema::new(
+                "Invalid token, must be non-empty without whitespaces or `#` prefix",
+            )
+            .code(StatusCode::BAD_REQUEST),
+        );
+    }
+    if let Some(bot) = body
+        .bots
+        .iter()
+        .find(|bot| validate_username(&clean_username(bot)).is_err())
+    {
+        let message = format!("Invalid bot username `{bot}`");
+        return write_ping_message(
+            res,
+            MessageSchema::new(&message).code(StatusCode::BAD_REQUEST),
+        );
+    }
+
+    let line = std::iter::once(token.to_owned())
+        .chain(
+            body.bots
+                .iter()
+                .map(|bot| format!("@{}", clean_username(bot))),
+        )
+        .collect::<Vec<_>>()
+        .join(" ");
+    let digest = sha256::digest(token);
+    if app_state
+        .access
+        .read()
+        .unwrap()
+        .tokens
+        .contains_key(&digest)
+    {
+        return write_ping_message(
+            res,
+            MessageSchema::new("The token already exists").code(StatusCode::CONFLICT),
+        );
+    }
+    if body.persist {
+        let Some(tokens_file) = app_state.tokens_files.first() else {
+            return write_ping_message(
+                res,
+                MessageSchema::new("The tokens can't be persisted, the tokens file is not a file")
+                    .code(StatusCode::BAD_REQUEST),
+            );
+        };
+        if let Err(err) = append_token_line(tokens_file, &line) {
+            log::error!("Faild to persist the new token: {err}");
+            return write_ping_message(
+                res,
+                MessageSchema::new("Faild to persist the token")
+                    .code(StatusCode::INTERNAL_SERVER_ERROR),
+            );
+        }
+    }
+    if !app_state.access.write().unwrap().add_token(&line) {
+        return write_ping_message(
+            res,
+            MessageSchema::new("The token already exists").code(StatusCode::CONFLICT),
+        );
+    }
+
+    log::info!("Added a new token `{digest}`");
+    res.status_code(StatusCode::CREATED);
+    write_json_body(
+        res,
+        TokenSchema {
+            id: digest,
+            bots: (!body.bots.is_empty()).then(|| {
+                body.bots
+                    .iter()
+                    .map(|bot| format!("@{}", clean_username(bot)))
+                    .collect()
+            }),
+        },
+    );
+}
+
+/// Shut down the API gracefully, as Ctrl-C, the admin token is required
+#[handler]
+async fn shutdown(req: &Request, res: &mut Response, depot: &mut Depot) {
+    let app_state = depot.obtain::<Arc<AppState>>().unwrap();
+    let sign_out = req.query::<bool>("sign_out").unwrap_or_default();
+    log::info!("Received a shutdown request, shutting down, sign out: {sign_out}");
+    if sign_out {
+        app_state.sign_out.store(true, Ordering::Relaxed);
+    }
+    // The in-flight requests are drained, so this response is sent before the shutdown
+    app_state.shutdown.cancel();
+    write_ping_message(
+        res,
+        MessageSchema::new("Shutting down").code(StatusCode::ACCEPTED),
+    );
+}
+
+/// Revoke an API token by its id, the admin token is required
+#[handler]
+async fn revoke_token(req: &Request, res: &mut Response, depot: &mut Depot) {
+    let app_state = depot.obtain::<Arc<AppState>>().unwrap();
+    let id = req.param::<String>("id").unwrap().to_lowercase();
+    let persist = req.query::<bool>("persist").unwrap_or_default();
+
+    if persist && app_state.tokens_files.is_empty() {
+        return write_ping_message(
+            res,
+            MessageSchema::new("The tokens can't be persisted, the tokens file is not a file")
+                .code(StatusCode::BAD_REQUEST),
+        );
+    }
+    // The token is revoked before persisting, so the unknown ids don't touch the tokens file
+    if !app_state.access.write().unwrap().remove_token(&id) {
+        return write_ping_message(
+            res,
+            MessageSchema::new("There is no token with this id").code(StatusCode::NOT_FOUND),
+        );
+    }
+    // The token can be in any of the tokens files
+    let tokens_files = if persist {
+        app_state.tokens_files.as_slice()
+    } else {
+        &[]
+    };
+    for tokens_file in tokens_files {
+        if let Err(err) = remove_token_lines(tokens_file, &id) {
+            log::error!(
+                "Faild to remove the revoked token `{id}` from `{}`: {err}",
+                tokens_file.display()
+            );
+            return write_ping_message(
+                res,
+                MessageSchema::new("The token is revoked, but faild to persist it")
+                    .code(StatusCode::INTERNAL_SERVER_ERROR),
+            );
+        }
+    }
+
+    log::info!("Revoked the token `{id}`");
+    write_ping_message(res, MessageSchema::new("The token is revoked"));
+}
+
+/// Append the token line to the tokens file
+fn append_token_line(tokens_file: &Path, line: &str) -> std::io::Result<()> {
+    let content = std::fs::read_to_string(tokens_file).unwrap_or_default();
+    let separator = if content.is_empty() || content.ends_with('\n') {
+        ""
+    } else {
+        "\n"
+    };
+    std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(tokens_file)?
+        .write_all(format!("{separator}{line}\n").as_bytes())
+}
+
+/// Remove the lines of the token from the tokens file by its sha256 digest, the other lines
+/// (e.g. the comments) are kept. The file is not written if the token is not in it
+fn remove_token_lines(tokens_file: &Path, digest: &str) -> std::io::Result<()> {
+    let content = std::fs::read_to_string(tokens_file)?;
+    let kept = content
+        .lines()
+        .filter(|line| {
+            line.split_whitespace()
+                .next()
+                .is_none_or(|token| sha256::digest(token) != digest)
+        })
+        .collect::<Vec<_>>();
+    if kept.len() == content.lines().count() {
+        return Ok(());
+    }
+    std::fs::write(tokens_file, kept.join("\n") + "\n")
+}
+
+/// Returns the error message if the token can't read the bot history, the username must be clean
+#[cfg(feature = "history")]
+fn history_access_error(
+    app_state: &AppState,
+    token: &TokenDigest,
+    bot_username: &str,
+) -> Option<MessageSchema<'static>> {
+    let access = app_state.access.read().unwrap();
+    if access.bot(bot_username).is_none() {
+        Some(
+            MessageSchema::new("Is not authorized to check the status of this bot")
+                .code(StatusCode::BAD_REQUEST),
+        )
+    } else if !access.is_in_scope(&token.0, bot_username) {
+        Some(
+            MessageSchema::new("The token is not allowed to check the status of this bot")
+                .code(StatusCode::FORBIDDEN),
+        )
+    } else {
+        None
+    }
+}
+
+#[cfg(feature = "history")]
+#[handler]
+async fn bot_history(req: &Request, res: &mut Response, depot: &mut Depot) {
+    let bot_username = req.param::<String>("bot_username").unwrap().to_lowercase();
+    let app_state = depot.obtain::<Arc<AppState>>().unwrap();
+    let token = depot.obtain::<TokenDigest>().unwrap();
+    // The route is only exposed with the history
+    let history = app_state.history.as_ref().unwrap();
+
+    let since = match req.query::<String>("since") {
+        Some(since) => match DateTime::parse_from_rfc3339(&since) {
+            Ok(since) => Some(since.with_timezone(&Utc)),
+            Err(_) => {
+                return write_ping_message(
+                    res,
+                    MessageSchema::new("Invalid `since`, expected RFC 3339 time")
+                        .code(StatusCode::BAD_REQUEST),
+                )
+            }
+        },
+        None => None,
+    };
+    if let Some(msg) = history_access_error(app_state, token, &bot_username) {
+        return write_ping_message(res, msg);
+    }
+
+    match history.query(&bot_username, since) {
+        Ok(entries) => write_json_body(res, entries),
+        Err(err) => {
+            log::error!("Faild to read the history of `@{bot_username}`: {err}");
+            write_ping_message(
+                res,
+                MessageSchema::new("Can't read the ping history")
+                    .code(StatusCode::INTERNAL_SERVER_ERROR),
+            )
+        }
+    }
+}
+
+#[cfg(feature = "history")]
+#[handler]
+async fn bot_uptime(req: &Request, res: &mut Response, depot: &mut Depot) {
+    let bot_username = req.param::<String>("bot_username").unwrap().to_lowercase();
+    let app_state = depot.obtain::<Arc<AppState>>().unwrap();
+    let token = depot.obtain::<TokenDigest>().unwrap();
+    // The route is only exposed with the history
+    let history = app_state.history.as_ref().unwrap();
+
+    let window = req
+        .query::<String>("window")
+        .unwrap_or_else(|| DEFAULT_UPTIME_WINDOW.to_owned());
+    let Some(duration) = crate::history::parse_window(&window) else {
+        return write_ping_message(
+            res,
+            MessageSchema::new("Invalid `window`, expected e.g. `1h`, `24h` or `7d`")
+                .code(StatusCode::BAD_REQUEST),
+        );
+    };
+    if let Some(msg) = history_access_error(app_state, token, &bot_username) {
+        return write_ping_message(res, msg);
     }
-}
 
-impl<'a> MessageSchema<'a> {
-    /// Create new [`Message`] instance with `200 OK` status
-    fn new(message: &'a str) -> Self {
-        Self {
-            message,
-            status: true,
-            status_code: StatusCode::OK,
+    let since = Utc::now()
+        .checked_sub_signed(duration)
+        .unwrap_or(DateTime::<Utc>::MIN_UTC);
+    match history.uptime(&bot_username, since) {
+        Ok((alive, samples)) => write_json_body(
+            res,
+            UptimeSchema {
+                bot: format!("@{bot_username}"),
+                window,
+                uptime: (samples > 0).then(|| alive as f64 / samples as f64),
+                samples,
+            },
+        ),
+        Err(err) => {
+            log::error!("Faild to read the history of `@{bot_username}`: {err}");
+            write_ping_message(
+                res,
+                MessageSchema::new("Can't read the ping history")
+                    .code(StatusCode::INTERNAL_SERVER_ERROR),
+            )
         }
     }
+}
 
-    /// Update the status code and status
-    fn code(mut self, status_code: StatusCode) -> Self {
-        self.status = status_code.is_success();
-        self.status_code = status_code;
-        self
+/// Copy the `token` query to the `Authorization` header, the browsers can't set the
+/// WebSocket headers
+#[handler]
+async fn token_from_query(req: &mut Request) {
+    if req.headers().contains_key(header::AUTHORIZATION) {
+        return;
+    }
+    if let Some(token) = req
+        .query::<String>("token")
+        .and_then(|token| HeaderValue::from_str(&token).ok())
+    {
+        req.headers_mut().insert(header::AUTHORIZATION, token);
     }
 }
 
-fn write_json_body(res: &mut Response, json_body: impl serde::Serialize) {
-    res.write_body(serde_json::to_string(&json_body).unwrap())
-        .ok();
+#[handler]
+async fn events_ws(
+    req: &mut Request,
+    res: &mut Response,
+    depot: &mut Depot,
+) -> Result<(), StatusError> {
+    let app_state = Arc::clone(depot.obtain::<Arc<AppState>>().unwrap());
+    let token = depot.obtain::<TokenDigest>().unwrap().clone();
+    let mut events = app_state.events.subscribe();
+
+    WebSocketUpgrade::new()
+        .upgrade(req, res, move |mut socket| async move {
+            log::info!("New WebSocket subscriber");
+            loop {
+                tokio::select! {
+                    event = events.recv() => match event {
+                        Ok(event) => {
+                            let is_in_scope = app_state
+                                .access
+                                .read()
+                                .unwrap()
+                                .is_in_scope(&token.0, event.bot.trim_start_matches('@'));
+                            if !is_in_scope {
+                                continue;
+                            }
+                            let msg = Message::text(serde_json::to_string(&event).unwrap());
+                            if socket.send(msg).await.is_err() {
+                                break;
+                            }
+                        }
+                        Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                            log::warn!("The WebSocket subscriber is slow, skipped {skipped} events");
+                        }
+                        Err(broadcast::error::RecvError::Closed) => break,
+                    },
+                    // The subscriber messages are ignored, only the close is handled
+                    msg = socket.recv() => match msg {
+                        Some(Ok(msg)) if !msg.is_close() => {}
+                        _ => break,
+                    },
+                }
+            }
+            log::info!("The WebSocket subscriber is disconnected");
+        })
+        .await
 }
 
 #[handler]
-async fn ping(req: &Request, res: &mut Response, depot: &mut Depot) {
-    let bot_username = req.param::<String>("bot_username").unwrap().to_lowercase();
+async fn prometheus_metrics(res: &mut Response, depot: &mut Depot) {
+    let app_state = depot.obtain::<Arc<AppState>>().unwrap();
+    res.headers_mut().insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("text/plain; version=0.0.4"),
+    );
+    res.write_body(app_state.metrics.render()).ok();
+}
+
+/// The liveness probe, or the readiness probe with `?ready=true`
+#[handler]
+async fn health(req: &Request, res: &mut Response, depot: &mut Depot) {
     let app_state = depot.obtain::<Arc<AppState>>().unwrap();
+    let telegram_authorized = tokio::time::timeout(
+        Duration::from_secs(HEALTH_CHECK_TIMEOUT),
+        app_state.pinger.is_authorized(),
+    )
+    .await
+    .unwrap_or(false);
+    let degraded = app_state.degraded.load(Ordering::Relaxed);
+    let ready = app_state.is_ready();
+    let usable = app_state.is_usable();
+    log::debug!(
+        "Health check, telegram authorized: {telegram_authorized}, degraded: {degraded}, ready: {ready}, usable: {usable}"
+    );
 
-    let msg = if !app_state.bots.contains(&bot_username) {
-        MessageSchema::new("Is not authorized to check the status of this bot")
-            .code(StatusCode::BAD_REQUEST)
-    } else if let Ok(telegram_id) =
-        crate::superbot::send_start(&app_state.tg_client, &bot_username).await
-    {
-        if crate::PINGED_BOTS.check(telegram_id) {
-            MessageSchema::new("Alive")
-        } else {
-            MessageSchema::new("No response from the bot").code(StatusCode::NOT_FOUND)
-        }
+    let mut status = telegram_authorized && !degraded && usable;
+    if req.query::<bool>("ready").unwrap_or_default() {
+        status &= ready;
+    }
+    res.status_code(if status {
+        StatusCode::OK
     } else {
-        MessageSchema::new("Cant send to the bot").code(StatusCode::INTERNAL_SERVER_ERROR)
-    };
-    res.status_code(msg.status_code);
-    write_json_body(res, msg);
+        StatusCode::SERVICE_UNAVAILABLE
+    });
+    write_json_body(
+        res,
+        HealthSchema {
+            status,
+            telegram_authorized,
+            degraded,
+            ready,
+            usable,
+        },
+    );
+}
+
+#[handler]
+async fn version(res: &mut Response) {
+    write_json_body(
+        res,
+        VersionSchema {
+            version: env!("CARGO_PKG_VERSION"),
+            git_sha: env!("TELEPINGBOT_GIT_SHA"),
+            build_time: env!("TELEPINGBOT_BUILD_TIME"),
+        },
+    );
+}
+
+/// The root path banner, for checking if the API is up in a browser
+#[handler]
+async fn banner(req: &Request, res: &mut Response, depot: &mut Depot) {
+    let app_state = depot.obtain::<Arc<AppState>>().unwrap();
+    // The path is the base path, so the hint has it too
+    let hint = app_state.banner_hint.clone().unwrap_or_else(|| {
+        format!(
+            "Use `{}/ping/@<bot_username>` with your token in the `Authorization` header",
+            req.uri().path().trim_end_matches('/')
+        )
+    });
+    write_json_body(
+        res,
+        BannerSchema {
+            name: env!("CARGO_PKG_NAME"),
+            version: env!("CARGO_PKG_VERSION"),
+            hint,
+        },
+    );
 }
 
 #[handler]
-async fn handle404(res: &mut Response, ctrl: &mut FlowCtrl) {
+async fn handle404(depot: &Depot, res: &mut Response, ctrl: &mut FlowCtrl) {
     if let Some(StatusCode::NOT_FOUND) = res.status_code {
+        crate::logger::sync_with_request_id(depot_request_id(depot), || {
+            log::debug!("Not found request")
+        });
         write_json_body(
             res,
             MessageSchema::new("Not Found").code(StatusCode::NOT_FOUND),
@@ -117,8 +2362,11 @@ async fn handle404(res: &mut Response, ctrl: &mut FlowCtrl) {
 }
 
 #[handler]
-async fn handle_server_errors(res: &mut Response, ctrl: &mut FlowCtrl) {
+async fn handle_server_errors(depot: &Depot, res: &mut Response, ctrl: &mut FlowCtrl) {
     if matches!(res.status_code, Some(status) if status.is_server_error()) {
+        crate::logger::sync_with_request_id(depot_request_id(depot), || {
+            log::error!("Server error: {:?}", res.status_code)
+        });
         write_json_body(
             res,
             MessageSchema::new("Server Error").code(StatusCode::INTERNAL_SERVER_ERROR),
@@ -131,56 +2379,1061 @@ async fn handle_server_errors(res: &mut Response, ctrl: &mut FlowCtrl) {
 async fn auth(req: &Request, res: &mut Response, depot: &mut Depot, ctrl: &mut FlowCtrl) {
     let app_state = depot.obtain::<Arc<AppState>>().unwrap();
     log::info!("New auth request");
-    if let Some(token) = req.headers().get("Authorization") {
+    let msg = if let Some(token) = req.headers().get("Authorization") {
         if let Ok(token) = token.to_str() {
-            if app_state.tokens.contains(&sha256::digest(token.trim())) {
+            let digest = app_state.access.read().unwrap().authorize_token(token);
+            if let Some(digest) = digest {
                 log::info!("The token is authorized");
+                depot
+                    .inject(TokenDigest(digest))
+                    .inject(AuthOutcome("AUTHORIZED"));
                 return;
             } else {
                 log::info!("Unauthorized token");
-                write_json_body(
-                    res,
-                    MessageSchema::new("Unauthorized").code(StatusCode::FORBIDDEN),
-                );
+                MessageSchema::new("Unauthorized")
+                    .code(StatusCode::FORBIDDEN)
+                    .error_code("AUTH_FORBIDDEN")
             }
         } else {
             log::info!("Invalid token value");
-            write_json_body(
-                res,
-                MessageSchema::new("Invalid token value").code(StatusCode::BAD_REQUEST),
-            );
+            MessageSchema::new("Invalid token value")
+                .code(StatusCode::BAD_REQUEST)
+                .error_code("AUTH_INVALID_VALUE")
         }
+    } else if app_state.client_cert_auth && req.scheme() == &salvo::http::uri::Scheme::HTTPS {
+        // The TLS listener only accepts the verified client certificates
+        log::info!("The client certificate is authorized");
+        depot
+            .inject(TokenDigest(CLIENT_CERT_IDENTITY.to_owned()))
+            .inject(AuthOutcome("AUTHORIZED"));
+        return;
     } else {
         log::info!("Missing `Authorization` header");
-        write_json_body(
-            res,
-            MessageSchema::new("Missing `Authorization` header").code(StatusCode::FORBIDDEN),
-        );
+        MessageSchema::new("Missing `Authorization` header")
+            .code(StatusCode::FORBIDDEN)
+            .error_code("AUTH_MISSING_HEADER")
+    };
+    if let Some(error_code) = msg.error_code {
+        depot.inject(AuthOutcome(error_code));
+    }
+    res.status_code(msg.status_code);
+    write_json_body(res, msg);
+    ctrl.skip_rest();
+}
+
+#[handler]
+async fn admin_auth(req: &Request, res: &mut Response, depot: &mut Depot, ctrl: &mut FlowCtrl) {
+    let app_state = depot.obtain::<Arc<AppState>>().unwrap();
+    let authorized = match (&app_state.admin_token, req.headers().get("Authorization")) {
+        (Some(admin_token), Some(token)) => token.to_str().is_ok_and(|token| {
+            bool::from(
+                admin_token
+                    .as_bytes()
+                    .ct_eq(sha256::digest(token.trim()).as_bytes()),
+            )
+        }),
+        _ => false,
+    };
+    if authorized {
+        log::info!("The admin token is authorized");
+        depot.inject(AuthOutcome("ADMIN"));
+        return;
+    }
+
+    log::info!("Unauthorized admin request");
+    let msg = MessageSchema::new("Unauthorized")
+        .code(StatusCode::FORBIDDEN)
+        .error_code("AUTH_FORBIDDEN");
+    depot.inject(AuthOutcome("AUTH_FORBIDDEN"));
+    res.status_code(msg.status_code);
+    write_json_body(res, msg);
+    ctrl.skip_rest();
+}
+
+/// Reject the client IPs that are not in the allowlist with `403`
+#[handler]
+async fn allow_ip(req: &Request, res: &mut Response, depot: &mut Depot, ctrl: &mut FlowCtrl) {
+    let app_state = depot.obtain::<Arc<AppState>>().unwrap();
+    let Some(ip_allowlist) = &app_state.ip_allowlist else {
+        return;
+    };
+    let forwarded_for = req
+        .headers()
+        .get_all("X-Forwarded-For")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .collect::<Vec<_>>()
+        .join(",");
+    let client_ip = req
+        .remote_addr()
+        .clone()
+        .into_std()
+        .map(|remote| ip_allowlist.client_ip(remote.ip(), Some(&forwarded_for)));
+    if matches!(client_ip, Some(ip) if ip_allowlist.is_allowed(ip)) {
+        return;
+    }
+
+    log::info!("Rejecting a request from not allowed IP: {client_ip:?}");
+    let msg = MessageSchema::new("The IP is not allowed")
+        .code(StatusCode::FORBIDDEN)
+        .error_code("IP_FORBIDDEN");
+    res.status_code(msg.status_code);
+    write_json_body(res, msg);
+    ctrl.skip_rest();
+}
+
+/// Reject the plaintext requests with `426` if `require_https` is set, the request is over
+/// HTTPS if it's served with TLS or our proxy forwarded it with `X-Forwarded-Proto: https`
+#[handler]
+async fn https_only(req: &Request, res: &mut Response, depot: &mut Depot, ctrl: &mut FlowCtrl) {
+    let app_state = depot.obtain::<Arc<AppState>>().unwrap();
+    if !app_state.require_https {
+        return;
     }
+    // The client can send its own header, so only the last entry is trusted, it's the one
+    // that our proxy added, e.g. `X-Forwarded-Proto: https, http` is plaintext
+    let forwarded_https = req
+        .headers()
+        .get("X-Forwarded-Proto")
+        .and_then(|proto| proto.to_str().ok())
+        .and_then(|proto| proto.rsplit(',').next())
+        .is_some_and(|proto| proto.trim().eq_ignore_ascii_case("https"));
+    if forwarded_https || req.scheme() == &salvo::http::uri::Scheme::HTTPS {
+        return;
+    }
+
+    log::info!("Rejecting a plaintext request, HTTPS is required");
+    let msg = MessageSchema::new("HTTPS is required")
+        .code(StatusCode::UPGRADE_REQUIRED)
+        .error_code("HTTPS_REQUIRED");
+    res.status_code(msg.status_code);
+    res.headers_mut().insert(
+        header::UPGRADE,
+        HeaderValue::from_static("TLS/1.2, HTTP/1.1"),
+    );
+    res.headers_mut()
+        .insert(header::CONNECTION, HeaderValue::from_static("Upgrade"));
+    write_json_body(res, msg);
     ctrl.skip_rest();
 }
 
 #[handler]
-async fn add_server_headers(res: &mut Response) {
-    let headers = res.headers_mut();
-    headers.insert(
+async fn limit_rate(res: &mut Response, depot: &mut Depot, ctrl: &mut FlowCtrl) {
+    let app_state = depot.obtain::<Arc<AppState>>().unwrap();
+    let token = depot.obtain::<TokenDigest>().unwrap();
+    if let Some(Err(seconds)) = app_state
+        .rate_limiter
+        .as_ref()
+        .map(|limiter| limiter.check(&token.0))
+    {
+        log::info!("The token is rate limited, retry after {seconds} seconds");
+        let msg = MessageSchema::new("Too many requests, try again later")
+            .code(StatusCode::TOO_MANY_REQUESTS)
+            .retry_after(seconds)
+            .error_code("RATE_LIMITED");
+        res.status_code(msg.status_code);
+        res.headers_mut()
+            .insert(header::RETRY_AFTER, HeaderValue::from(seconds));
+        write_json_body(res, msg);
+        ctrl.skip_rest();
+    }
+}
+
+/// Returns a new random UUID v4
+fn new_uuid_v4() -> String {
+    let mut bytes: [u8; 16] = rand::random();
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex: String = bytes.iter().map(|b| format!("{b:02x}")).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+/// Returns if the client request id is usable, it will be in the logs and the headers
+fn is_valid_request_id(request_id: &str) -> bool {
+    !request_id.is_empty()
+        && request_id.len() <= MAX_REQUEST_ID_LEN
+        && request_id.bytes().all(|b| b.is_ascii_graphic())
+}
+
+/// Returns the request id of the request, for the catchers
+fn depot_request_id(depot: &Depot) -> String {
+    depot
+        .obtain::<RequestId>()
+        .map(|id| id.0.clone())
+        .unwrap_or_default()
+}
+
+#[handler]
+async fn add_request_id(
+    req: &mut Request,
+    depot: &mut Depot,
+    res: &mut Response,
+    ctrl: &mut FlowCtrl,
+) {
+    let request_id = req
+        .header::<String>(REQUEST_ID_HEADER)
+        .filter(|id| is_valid_request_id(id))
+        .unwrap_or_else(new_uuid_v4);
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        res.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    depot.inject(RequestId(request_id.clone()));
+    crate::logger::with_request_id(request_id, ctrl.call_next(req, depot, res)).await;
+}
+
+/// Adds the server headers to all the responses, the content type, `X-Powered-By` and the
+/// custom headers
+#[derive(Debug)]
+pub(crate) struct ServerHeaders {
+    hide_powered_by: bool,
+    headers: Vec<(HeaderName, HeaderValue)>,
+}
+
+impl ServerHeaders {
+    /// Create new [`ServerHeaders`], returns an error if a custom header is invalid
+    pub(crate) fn new(
+        hide_powered_by: bool,
+        headers: Vec<(String, String)>,
+    ) -> crate::Result<Self> {
+        let headers = headers
+            .into_iter()
+            .map(|(name, value)| {
+                let invalid = || Error::Config(format!("Invalid header `{name}: {value}`"));
+                Ok((
+                    HeaderName::from_bytes(name.trim().as_bytes()).map_err(|_| invalid())?,
+                    HeaderValue::from_str(value.trim()).map_err(|_| invalid())?,
+                ))
+            })
+            .collect::<crate::Result<_>>()?;
+        Ok(Self {
+            hide_powered_by,
+            headers,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler for ServerHeaders {
+    async fn handle(
+        &self,
+        _req: &mut Request,
+        _depot: &mut Depot,
+        res: &mut Response,
+        _ctrl: &mut FlowCtrl,
+    ) {
+        let headers = res.headers_mut();
+        headers.insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/json"),
+        );
+        if !self.hide_powered_by {
+            // Yeah, Rusty programmer
+            headers.insert("X-Powered-By", HeaderValue::from_static("Rust/Salvo"));
+        }
+        for (name, value) in &self.headers {
+            headers.insert(name.clone(), value.clone());
+        }
+    }
+}
+
+#[handler]
+async fn openapi_json(res: &mut Response, depot: &mut Depot) {
+    let base_path = depot.obtain::<BasePath>().unwrap();
+    write_json_body(res, crate::openapi::spec(&base_path.0));
+}
+
+#[handler]
+async fn swagger_ui(res: &mut Response) {
+    res.headers_mut().insert(
         header::CONTENT_TYPE,
-        HeaderValue::from_static("application/json"),
+        HeaderValue::from_static("text/html; charset=utf-8"),
     );
-    // Yeah, Rusty programmer
-    headers.insert("X-Powered-By", HeaderValue::from_static("Rust/Salvo"));
+    res.write_body(crate::openapi::SWAGGER_UI).ok();
 }
 
-pub(crate) fn service(app_state: AppState) -> Service {
-    let router = Router::new()
-        .hoop(Logger::new())
-        .hoop(affix::inject(Arc::new(app_state)))
-        .hoop(add_server_headers)
+/// The preflight requests are answered by the [`Cors`] hoop, this is to match them
+#[handler]
+async fn preflight() {}
+
+pub(crate) fn service(
+    app_state: Arc<AppState>,
+    cors: Option<Cors>,
+    base_path: BasePath,
+    access_log: AccessLog,
+    server_headers: ServerHeaders,
+) -> Service {
+    let mut router = Router::new().hoop(add_request_id).hoop(access_log);
+    if let Some(cors) = cors {
+        router = router
+            .hoop(cors)
+            .push(Router::with_path("<**>").options(preflight));
+    }
+    let admin_enabled = app_state.admin_token.is_some();
+    let mut api = if base_path.0.is_empty() {
+        Router::new()
+    } else {
+        Router::with_path(&base_path.0)
+    };
+    // The metrics, health check and docs are public, so Prometheus and the
+    // orchestrators can use them without a token
+    api = api
+        .get(banner)
+        .push(Router::with_path("metrics").get(prometheus_metrics))
+        .push(Router::with_path("health").get(health))
+        .push(Router::with_path("version").get(version))
+        .push(Router::with_path("openapi.json").get(openapi_json))
+        .push(Router::with_path("docs").get(swagger_ui));
+    // The admin endpoints are only exposed with the admin token
+    if admin_enabled {
+        api = api
+            .push(
+                Router::with_path("debug/pings")
+                    .hoop(admin_auth)
+                    .get(debug_pings),
+            )
+            .push(
+                Router::with_path("tokens")
+                    .hoop(admin_auth)
+                    .post(add_token)
+                    .push(Router::with_path("<id>").delete(revoke_token)),
+            )
+            .push(
+                Router::with_path("shutdown")
+                    .hoop(admin_auth)
+                    .post(shutdown),
+            );
+    }
+    #[allow(unused_mut)]
+    let mut bots_router = Router::new()
         .hoop(auth)
-        .push(Router::with_path("ping/@<bot_username>").get(ping));
+        .hoop(limit_rate)
+        .push(Router::with_path("bots").get(list_bots))
+        .push(Router::with_path("ping").post(batch_ping))
+        .push(
+            Router::with_path("ping/@<bot_username>")
+                .get(ping)
+                .head(ping),
+        )
+        .push(Router::with_path("ping/id/<telegram_id>").get(ping_by_id))
+        .push(
+            Router::with_path("ping/<bot_username>")
+                .get(ping)
+                .head(ping),
+        );
+    // The history is only exposed with `TELEPINGBOT_DB_PATH`
+    #[cfg(feature = "history")]
+    if app_state.history.is_some() {
+        bots_router = bots_router
+            .push(Router::with_path("history/@<bot_username>").get(bot_history))
+            .push(Router::with_path("uptime/@<bot_username>").get(bot_uptime));
+    }
+    api = api.push(bots_router);
+    api = api.push(
+        Router::with_path("ws")
+            .hoop(token_from_query)
+            .hoop(auth)
+            .hoop(limit_rate)
+            .get(events_ws),
+    );
+    let router = router
+        .hoop(affix::inject(app_state).inject(base_path))
+        .hoop(server_headers)
+        .hoop(allow_ip)
+        .hoop(https_only)
+        .push(api);
     Service::new(router).catcher(
         Catcher::default()
             .hoop(handle404)
             .hoop(handle_server_errors),
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use salvo::{
+        http::ReqBody,
+        test::{RequestBuilder, ResponseExt, TestClient},
+    };
+    use serde_json::Value;
+
+    use super::*;
+    use crate::Pings;
+
+    /// How a bot of the [`FakePinger`] behaves
+    #[derive(Debug, Clone, Copy)]
+    enum FakeBot {
+        Alive,
+        NoResponse,
+        Blocked,
+        /// The ping never finishes
+        Hang,
+        /// The bot response after a short delay
+        Slow,
+        /// The username is a user account
+        NotBot,
+    }
+
+    /// A pinger without telegram, each bot behaves as it's configured
+    #[derive(Debug, Default)]
+    struct FakePinger {
+        bots: HashMap<String, FakeBot>,
+        pings: Pings,
+        /// The pings that are running now
+        in_flight: AtomicUsize,
+        /// The most pings that run at once
+        max_in_flight: AtomicUsize,
+    }
+
+    impl FakePinger {
+        fn new(bots: &[(&str, FakeBot)]) -> Self {
+            Self {
+                bots: bots
+                    .iter()
+                    .map(|(bot, fake)| ((*bot).to_owned(), *fake))
+                    .collect(),
+                ..Self::default()
+            }
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl BotPinger for FakePinger {
+        async fn ping(
+            &self,
+            bot: &BotConfig,
+            _response_timeout: u64,
+        ) -> crate::Result<PingOutcome> {
+            match self.bots.get(&bot.username).copied() {
+                Some(FakeBot::Alive) => Ok(PingOutcome::Alive {
+                    latency_ms: 42,
+                    telegram_id: 1,
+                }),
+                Some(FakeBot::NoResponse) | None => Ok(PingOutcome::NoResponse),
+                Some(FakeBot::Blocked) => Err(Error::Blocked("USER_IS_BLOCKED".to_owned())),
+                Some(FakeBot::Hang) => std::future::pending().await,
+                Some(FakeBot::NotBot) => Err(Error::NotBot(bot.username.clone())),
+                Some(FakeBot::Slow) => {
+                    let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                    self.max_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                    self.in_flight.fetch_sub(1, Ordering::SeqCst);
+                    Ok(PingOutcome::Alive {
+                        latency_ms: 50,
+                        telegram_id: 1,
+                    })
+                }
+            }
+        }
+
+        async fn resolve(&self, _bot_username: &str) -> crate::Result<u64> {
+            Ok(1)
+        }
+
+        async fn is_authorized(&self) -> bool {
+            true
+        }
+
+        fn pings(&self) -> &Pings {
+            &self.pings
+        }
+    }
+
+    /// Returns a ready state of the fake bots, `token` can ping all of them and `scoped` only
+    /// the first one
+    async fn app_state(bots: &[(&str, FakeBot)]) -> AppState {
+        app_state_of(Arc::new(FakePinger::new(bots)), bots).await
+    }
+
+    /// Like [`app_state`], with the given pinger of the bots
+    async fn app_state_of(pinger: Arc<FakePinger>, bots: &[(&str, FakeBot)]) -> AppState {
+        let app_state = AppState::new(
+            bots.iter().map(|(bot, _)| format!("@{bot}")).collect(),
+            vec!["token".to_owned(), format!("scoped @{}", bots[0].0)],
+            pinger,
+            1,
+            1,
+            Arc::new(Metrics::default()),
+            None,
+        );
+        app_state.warm_up(Duration::ZERO, 0).await;
+        app_state
+    }
+
+    fn test_service(app_state: AppState) -> Service {
+        service(
+            Arc::new(app_state),
+            None,
+            BasePath(String::new()),
+            AccessLog::from_env().unwrap(),
+            ServerHeaders::new(false, Vec::new()).unwrap(),
+        )
+    }
+
+    /// Send the request with the token, returns the status code and the json body
+    async fn send(service: &Service, request: RequestBuilder, token: &str) -> (u16, Value) {
+        let mut res = request
+            .add_header("Authorization", token, true)
+            .send(service)
+            .await;
+        let body = res.take_json::<Value>().await.unwrap();
+        (res.status_code.unwrap().as_u16(), body)
+    }
+
+    #[tokio::test]
+    async fn ping_the_alive_bot() {
+        let service = test_service(app_state(&[("alive_bot", FakeBot::Alive)]).await);
+        let (status, body) = send(
+            &service,
+            TestClient::get("http://127.0.0.1/ping/@alive_bot"),
+            "token",
+        )
+        .await;
+        assert_eq!(status, 200);
+        assert_eq!(body["alive"], true);
+        assert_eq!(body["latency_ms"], 42);
+        assert_eq!(body["telegram_id"], 1);
+    }
+
+    #[tokio::test]
+    async fn ping_with_unauthorized_token() {
+        let service = test_service(
+            app_state(&[("alive_bot", FakeBot::Alive), ("other_bot", FakeBot::Alive)]).await,
+        );
+        let (status, body) = send(
+            &service,
+            TestClient::get("http://127.0.0.1/ping/@alive_bot"),
+            "wrong_token",
+        )
+        .await;
+        assert_eq!(status, 403);
+        assert_eq!(body["error_code"], "AUTH_FORBIDDEN");
+
+        // The scoped token can ping only its bots
+        let (status, body) = send(
+            &service,
+            TestClient::get("http://127.0.0.1/ping/@other_bot"),
+            "scoped",
+        )
+        .await;
+        assert_eq!(status, 403);
+        assert_eq!(
+            body["message"],
+            "The token is not allowed to check the status of this bot"
+        );
+        let (status, _) = send(
+            &service,
+            TestClient::get("http://127.0.0.1/ping/@alive_bot"),
+            "scoped",
+        )
+        .await;
+        assert_eq!(status, 200);
+
+        // The bot is not in the bots list
+        let (status, body) = send(
+            &service,
+            TestClient::get("http://127.0.0.1/ping/@unknown_bot"),
+            "token",
+        )
+        .await;
+        assert_eq!(status, 400);
+        assert_eq!(
+            body["message"],
+            "Is not authorized to check the status of this bot"
+        );
+    }
+
+    #[tokio::test]
+    async fn ping_the_bot_without_response() {
+        let service = test_service(app_state(&[("silent_bot", FakeBot::NoResponse)]).await);
+        let (status, body) = send(
+            &service,
+            TestClient::get("http://127.0.0.1/ping/@silent_bot"),
+            "token",
+        )
+        .await;
+        assert_eq!(status, 404);
+        assert_eq!(body["alive"], false);
+        assert_eq!(body["message"], "No response from the bot");
+    }
+
+    #[tokio::test]
+    async fn ping_with_error() {
+        let service = test_service(
+            app_state(&[("blocked_bot", FakeBot::Blocked)])
+                .await
+                .error_detail(true),
+        );
+        let (status, body) = send(
+            &service,
+            TestClient::get("http://127.0.0.1/ping/@blocked_bot"),
+            "token",
+        )
+        .await;
+        assert_eq!(status, 403);
+        assert_eq!(body["alive"], Value::Null);
+        assert_eq!(body["error_code"], "TELEGRAM_BLOCKED");
+        assert_eq!(body["error_detail"], "USER_IS_BLOCKED");
+    }
+
+    #[tokio::test]
+    async fn ping_not_bot() {
+        let service = test_service(
+            app_state(&[("lonely_userbot", FakeBot::NotBot)])
+                .await
+                .error_detail(true),
+        );
+        let (status, body) = send(
+            &service,
+            TestClient::get("http://127.0.0.1/ping/@lonely_userbot"),
+            "token",
+        )
+        .await;
+        assert_eq!(status, 500);
+        assert_eq!(body["alive"], Value::Null);
+        assert_eq!(body["message"], "The username is not a bot account");
+        assert_eq!(body["error_detail"], "NOT_A_BOT");
+    }
+
+    #[tokio::test]
+    async fn ping_timeout() {
+        let app_state = app_state(&[("hang_bot", FakeBot::Hang)])
+            .await
+            .error_detail(true);
+        let bot = app_state
+            .access
+            .read()
+            .unwrap()
+            .bot("hang_bot")
+            .cloned()
+            .unwrap();
+        assert!(matches!(
+            probe_bot(&app_state, &bot).await,
+            Err(Error::Timeout)
+        ));
+
+        let service = test_service(app_state);
+        let (status, body) = send(
+            &service,
+            TestClient::get("http://127.0.0.1/ping/@hang_bot"),
+            "token",
+        )
+        .await;
+        assert_eq!(status, 504);
+        assert_eq!(body["message"], "Timeout while pinging the bot");
+        assert_eq!(body["error_detail"], "PING_TIMEOUT");
+    }
+
+    #[tokio::test]
+    async fn cancelled_ping_is_forgotten() {
+        let service = test_service(app_state(&[("slow_bot", FakeBot::Slow)]).await.cooldown(60));
+        let ping_slow_bot = || TestClient::get("http://127.0.0.1/ping/@slow_bot");
+        // The client disconnects before the bot response
+        let cancelled = tokio::time::timeout(
+            Duration::from_millis(10),
+            send(&service, ping_slow_bot(), "token"),
+        )
+        .await;
+        assert!(cancelled.is_err());
+
+        let (status, body) = send(&service, ping_slow_bot(), "token").await;
+        assert_eq!(status, 200);
+        assert!(body.get("checked_at").is_none());
+        // In the cooldown the last result is returned
+        let (status, body) = send(&service, ping_slow_bot(), "token").await;
+        assert_eq!(status, 200);
+        assert!(body["checked_at"].is_string());
+    }
+
+    #[tokio::test]
+    async fn expired_cooldowns_are_forgotten() {
+        let app_state = app_state(&[
+            ("first_bot", FakeBot::Alive),
+            ("second_bot", FakeBot::Alive),
+        ])
+        .await
+        .cooldown(1);
+        let probe = |bot: &str| {
+            let bot = app_state.access.read().unwrap().bot(bot).cloned().unwrap();
+            match app_state.start_probe(&bot) {
+                Ok(probe) => probe.finish(&Ok(PingOutcome::NoResponse)),
+                Err(_) => panic!("`@{}` is in its cooldown", bot.username),
+            }
+        };
+        probe("first_bot");
+        probe("second_bot");
+        assert_eq!(app_state.last_probes.lock().unwrap().len(), 2);
+
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        probe("first_bot");
+        let last_probes = app_state.last_probes.lock().unwrap();
+        assert_eq!(last_probes.keys().collect::<Vec<_>>(), ["first_bot"]);
+    }
+
+    #[tokio::test]
+    async fn max_concurrency_limits_the_pings() {
+        let bots: Vec<(String, FakeBot)> = (0..8)
+            .map(|idx| (format!("slow{idx}_bot"), FakeBot::Slow))
+            .collect();
+        let bots: Vec<(&str, FakeBot)> = bots
+            .iter()
+            .map(|(bot, fake)| (bot.as_str(), *fake))
+            .collect();
+        let pinger = Arc::new(FakePinger::new(&bots));
+        let app_state = app_state_of(Arc::clone(&pinger), &bots)
+            .await
+            .max_concurrency(Some(3));
+        let configs: Vec<BotConfig> = app_state.access.read().unwrap().bots.clone();
+
+        let results =
+            futures_util::future::join_all(configs.iter().map(|bot| probe_bot(&app_state, bot)))
+                .await;
+        assert!(results
+            .iter()
+            .all(|result| matches!(result, Ok(PingOutcome::Alive { .. }))));
+        assert_eq!(pinger.max_in_flight.load(Ordering::SeqCst), 3);
+        assert_eq!(pinger.in_flight.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn https_only_trusts_the_last_forwarded_proto() {
+        let service = test_service(
+            app_state(&[("alive_bot", FakeBot::Alive)])
+                .await
+                .require_https(true),
+        );
+        let status = |proto: Option<&'static str>| {
+            let mut request = TestClient::get("http://127.0.0.1/version");
+            if let Some(proto) = proto {
+                request = request.add_header("X-Forwarded-Proto", proto, true);
+            }
+            let service = &service;
+            async move { request.send(service).await.status_code.unwrap().as_u16() }
+        };
+        assert_eq!(status(Some("https")).await, 200);
+        assert_eq!(status(Some("http, https")).await, 200);
+        assert_eq!(status(Some("http")).await, 426);
+        assert_eq!(status(Some("https, http")).await, 426);
+        assert_eq!(status(None).await, 426);
+    }
+
+    /// Returns the batch ping body of the bots, padded to the given size
+    fn batch_body(bots: &[&str], size: usize) -> String {
+        let body = serde_json::json!({ "bots": bots }).to_string();
+        format!("{body:<size$}")
+    }
+
+    /// Stream the body in small chunks, without `Content-Length`
+    fn streamed_body(body: String) -> ReqBody {
+        let chunks: Vec<Result<_, salvo::BoxedError>> = body
+            .into_bytes()
+            .chunks(16)
+            .map(|chunk| Ok(hyper::body::Frame::data(chunk.to_vec().into())))
+            .collect();
+        ReqBody::Boxed(Box::pin(http_body_util::StreamBody::new(
+            futures_util::stream::iter(chunks),
+        )))
+    }
+
+    #[tokio::test]
+    async fn batch_ping_max_bots() {
+        let service = test_service(
+            app_state(&[
+                ("first_bot", FakeBot::Alive),
+                ("second_bot", FakeBot::Alive),
+            ])
+            .await
+            .batch_limits(2, 1024),
+        );
+        let (status, body) = send(
+            &service,
+            TestClient::post("http://127.0.0.1/ping")
+                .raw_json(batch_body(&["@first_bot", "@second_bot"], 0)),
+            "token",
+        )
+        .await;
+        assert_eq!(status, 200);
+        assert_eq!(body.as_array().unwrap().len(), 2);
+
+        let (status, body) = send(
+            &service,
+            TestClient::post("http://127.0.0.1/ping")
+                .raw_json(batch_body(&["@first_bot", "@second_bot", "@first_bot"], 0)),
+            "token",
+        )
+        .await;
+        assert_eq!(status, 400);
+        assert_eq!(body["error_code"], "BATCH_TOO_MANY_BOTS");
+    }
+
+    #[tokio::test]
+    async fn batch_ping_unauthorized_bots() {
+        let service = test_service(
+            app_state(&[
+                ("first_bot", FakeBot::Alive),
+                ("second_bot", FakeBot::Alive),
+            ])
+            .await,
+        );
+        // Some bots are authorized and some are not
+        let (status, body) = send(
+            &service,
+            TestClient::post("http://127.0.0.1/ping")
+                .raw_json(batch_body(&["@first_bot", "@second_bot"], 0)),
+            "scoped",
+        )
+        .await;
+        assert_eq!(status, 207);
+        assert_eq!(body[0]["authorized"], true);
+        assert_eq!(body[1]["authorized"], false);
+        assert_eq!(body[1]["status_code"], 403);
+
+        // All the bots are out of the token scope
+        let (status, body) = send(
+            &service,
+            TestClient::post("http://127.0.0.1/ping")
+                .raw_json(batch_body(&["@second_bot", "@unknown_bot"], 0)),
+            "scoped",
+        )
+        .await;
+        assert_eq!(status, 403);
+        assert_eq!(body.as_array().unwrap().len(), 2);
+
+        // All the bots are not in the bots list
+        let (status, body) = send(
+            &service,
+            TestClient::post("http://127.0.0.1/ping")
+                .raw_json(batch_body(&["@unknown_bot", "@other_bot"], 0)),
+            "token",
+        )
+        .await;
+        assert_eq!(status, 400);
+        assert_eq!(body[0]["authorized"], false);
+        assert_eq!(body[1]["status_code"], 400);
+    }
+
+    #[tokio::test]
+    async fn batch_ping_max_body() {
+        const MAX_BODY: usize = 128;
+        let service = test_service(
+            app_state(&[("alive_bot", FakeBot::Alive)])
+                .await
+                .batch_limits(10, MAX_BODY),
+        );
+        let request = || {
+            TestClient::post("http://127.0.0.1/ping").add_header(
+                header::CONTENT_TYPE,
+                "application/json",
+                true,
+            )
+        };
+        for (size, expected) in [(MAX_BODY, 200), (MAX_BODY + 1, 413)] {
+            let body = batch_body(&["@alive_bot"], size);
+            assert_eq!(body.len(), size);
+
+            // The `Content-Length` fast path
+            let (status, _) = send(
+                &service,
+                request()
+                    .add_header(header::CONTENT_LENGTH, size.to_string(), true)
+                    .body(body.clone()),
+                "token",
+            )
+            .await;
+            assert_eq!(status, expected, "{size} bytes with `Content-Length`");
+
+            // The body is read until the limit
+            let (status, _) = send(&service, request().body(body.clone()), "token").await;
+            assert_eq!(status, expected, "{size} bytes body");
+
+            let (status, body) = send(&service, request().body(streamed_body(body)), "token").await;
+            assert_eq!(status, expected, "{size} bytes streamed body");
+            if expected == 413 {
+                assert_eq!(body["error_code"], "BATCH_TOO_LARGE");
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn added_token_is_authorized_until_revoked() {
+        let tokens_file =
+            std::env::temp_dir().join(format!("telepingbot-tokens-{}.txt", std::process::id()));
+        std::fs::write(&tokens_file, "# The tokens\ntoken\n").unwrap();
+        let service = test_service(
+            app_state(&[("alive_bot", FakeBot::Alive)])
+                .await
+                .admin_token(Some("admin".to_owned()))
+                .tokens_files(vec![tokens_file.clone()]),
+        );
+        let ping_alive_bot = || TestClient::get("http://127.0.0.1/ping/@alive_bot");
+
+        let (status, body) = send(
+            &service,
+            TestClient::post("http://127.0.0.1/tokens")
+                .json(&serde_json::json!({"token": "new_token", "persist": true})),
+            "admin",
+        )
+        .await;
+        assert_eq!(status, 201);
+        let id = body["id"].as_str().unwrap().to_owned();
+        assert_eq!(id, sha256::digest("new_token"));
+        // The new token is authorized right away
+        assert_eq!(send(&service, ping_alive_bot(), "new_token").await.0, 200);
+        assert_eq!(
+            std::fs::read_to_string(&tokens_file).unwrap(),
+            "# The tokens\ntoken\nnew_token\n"
+        );
+
+        let revoke = |id: &str| {
+            TestClient::delete(format!("http://127.0.0.1/tokens/{id}")).query("persist", true)
+        };
+        assert_eq!(send(&service, revoke(&id), "admin").await.0, 200);
+        assert_eq!(send(&service, ping_alive_bot(), "new_token").await.0, 403);
+        assert_eq!(
+            std::fs::read_to_string(&tokens_file).unwrap(),
+            "# The tokens\ntoken\n"
+        );
+
+        // The unknown token doesn't touch the tokens file
+        std::fs::remove_file(&tokens_file).unwrap();
+        assert_eq!(send(&service, revoke(&id), "admin").await.0, 404);
+        assert!(!tokens_file.exists());
+    }
+
+    #[tokio::test]
+    async fn batch_ping_invalid_body() {
+        let service = test_service(app_state(&[("alive_bot", FakeBot::Alive)]).await);
+        let body = batch_body(&["@alive_bot"], 0);
+
+        let (status, body) = send(
+            &service,
+            TestClient::post("http://127.0.0.1/ping")
+                .add_header(header::CONTENT_TYPE, "text/plain", true)
+                .body(body),
+            "token",
+        )
+        .await;
+        assert_eq!(status, 400);
+        assert!(body.get("error_code").is_none());
+
+        // Faild to read the body, e.g. the client disconnected
+        let chunks: Vec<Result<hyper::body::Frame<salvo::hyper::body::Bytes>, salvo::BoxedError>> = vec![
+            Ok(hyper::body::Frame::data("{\"bots\": ".into())),
+            Err("connection reset".into()),
+        ];
+        let (status, body) = send(
+            &service,
+            TestClient::post("http://127.0.0.1/ping")
+                .add_header(header::CONTENT_TYPE, "application/json", true)
+                .body(ReqBody::Boxed(Box::pin(http_body_util::StreamBody::new(
+                    futures_util::stream::iter(chunks),
+                )))),
+            "token",
+        )
+        .await;
+        assert_eq!(status, 400);
+        assert!(body.get("error_code").is_none());
+    }
+
+    #[tokio::test]
+    async fn revoked_token_is_removed_from_all_the_tokens_files() {
+        let tokens_file = |name: &str| {
+            std::env::temp_dir().join(format!("telepingbot-{name}-{}.txt", std::process::id()))
+        };
+        let (first_file, second_file) = (tokens_file("first"), tokens_file("second"));
+        std::fs::write(&first_file, "token\n").unwrap();
+        std::fs::write(&second_file, "# The team tokens\nteam_token @alive_bot\n").unwrap();
+        let app_state = app_state(&[("alive_bot", FakeBot::Alive)])
+            .await
+            .admin_token(Some("admin".to_owned()))
+            .tokens_files(vec![first_file.clone(), second_file.clone()]);
+        app_state
+            .access
+            .write()
+            .unwrap()
+            .add_token("team_token @alive_bot");
+        let service = test_service(app_state);
+
+        let (status, _) = send(
+            &service,
+            TestClient::delete(format!(
+                "http://127.0.0.1/tokens/{}",
+                sha256::digest("team_token")
+            ))
+            .query("persist", true),
+            "admin",
+        )
+        .await;
+        assert_eq!(status, 200);
+        assert_eq!(std::fs::read_to_string(&first_file).unwrap(), "token\n");
+        assert_eq!(
+            std::fs::read_to_string(&second_file).unwrap(),
+            "# The team tokens\n"
+        );
+        std::fs::remove_file(first_file).unwrap();
+        std::fs::remove_file(second_file).unwrap();
+    }
+
+    #[test]
+    fn validate_username_rules() {
+        const LENGTH: &str = "The bot username must be 5 to 32 characters";
+        const CHARS: &str =
+            "The bot username must start with a letter and contain only letters, numbers and `_`";
+        const SUFFIX: &str = "The bot username must end with `bot`";
+
+        let validate = |username: &str| validate_username(&clean_username(username));
+        assert_eq!(validate("@a_bot"), Ok(()));
+        assert_eq!(validate("@abot"), Err(LENGTH));
+        assert_eq!(validate(&format!("@{}bot", "a".repeat(2))), Ok(()));
+        assert_eq!(validate(&format!("@{}bot", "a".repeat(29))), Ok(()));
+        assert_eq!(validate(&format!("@{}bot", "a".repeat(30))), Err(LENGTH));
+        assert_eq!(validate("@bot"), Err(LENGTH));
+        assert_eq!(validate("_"), Err(LENGTH));
+        assert_eq!(validate("@12bot"), Err(CHARS));
+        assert_eq!(validate("@_somebot"), Err(CHARS));
+        assert_eq!(validate("@some-bot"), Err(CHARS));
+        assert_eq!(validate("@somebody"), Err(SUFFIX));
+        // The suffix is case insensitive, even without cleaning the username
+        assert_eq!(validate("@SomeBot"), Ok(()));
+        assert_eq!(validate_username("SomeBot"), Ok(()));
+        assert_eq!(validate_username("SomeBOT"), Ok(()));
+    }
+
+    #[test]
+    fn access_lists_dedupe_the_bots_case_insensitively() {
+        let access = AccessLists::new(
+            vec!["@My_Bot /status".to_owned(), "@my_bot /start".to_owned()],
+            vec!["token @MY_BOT".to_owned()],
+        );
+        assert_eq!(access.bots.len(), 1);
+        // The first one is kept
+        let bot = access.bot("my_bot").unwrap();
+        assert_eq!(bot.probe, Probe::Command("/status".to_owned()));
+        assert!(access.is_in_scope(&sha256::digest("token"), "my_bot"));
+    }
+
+    #[test]
+    fn authorize_token_compares_all_the_digests() {
+        let access = AccessLists::new(
+            Vec::new(),
+            vec!["first_token".to_owned(), "other_token".to_owned()],
+        );
+        // The same length tokens, only their digests are compared
+        assert_eq!(
+            access.authorize_token("other_token"),
+            Some(sha256::digest("other_token"))
+        );
+        assert_eq!(access.authorize_token("wrong_token"), None);
+        assert_eq!(
+            access.authorize_token(" first_token "),
+            Some(sha256::digest("first_token"))
+        );
+        assert_eq!(access.authorize_token(""), None);
+    }
+}
@@ -0,0 +1,142 @@
+// A simple API to ping telegram bots and returns if it's online or not.
+// Copyright (C) 2023  Awiteb <awitb@hotmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::{collections::BTreeMap, fs};
+
+use crate::errors::{Error, Result};
+
+/// The config file, an alternative to the environment variables and the bots and
+/// tokens files. The optional fields fall back to the environment variables
+#[derive(Debug, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct FileConfig {
+    pub api_id: i32,
+    pub api_hash: String,
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    /// The time to wait the bot response, in seconds
+    pub response_timeout: Option<u64>,
+    /// The bots lines, same as the `bots.txt` lines
+    pub bots: Vec<String>,
+    /// The tokens lines, same as the `tokens.txt` lines
+    pub tokens: Vec<String>,
+    /// The custom headers of the responses, by their names
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+}
+
+/// Returns the config file path from `--config` argument or `TELEPINGBOT_CONFIG`
+/// environment variable
+pub(crate) fn config_path() -> Option<String> {
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--config" {
+            return args.next();
+        }
+        if let Some(path) = arg.strip_prefix("--config=") {
+            return Some(path.to_owned());
+        }
+    }
+    std::env::var("TELEPINGBOT_CONFIG")
+        .ok()
+        .filter(|path| !path.is_empty())
+}
+
+/// Returns the invalid field error
+fn invalid(field: &str, reason: &str) -> Error {
+    Error::Config(format!("Invalid config field `{field}`: {reason}"))
+}
+
+/// Trim the lines and skip the empty ones
+fn clean_lines(lines: Vec<String>) -> Vec<String> {
+    lines
+        .into_iter()
+        .map(|line| line.trim().to_owned())
+        .filter(|line| !line.is_empty())
+        .collect()
+}
+
+impl FileConfig {
+    /// Load the config from the TOML file
+    pub(crate) fn load(path: &str) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .map_err(|err| Error::Config(format!("Faild to read `{path}`: {err}")))?;
+        Self::from_toml(&content)
+            .map_err(|err| Error::Config(format!("Invalid config file `{path}`: {err}")))
+    }
+
+    /// Parse the config, the fields types are checked by serde, then their values
+    fn from_toml(content: &str) -> Result<Self> {
+        let config: Self = toml_edit::de::from_str(content)
+            .map_err(|err| Error::Config(err.to_string().trim_end().to_owned()))?;
+        if config.api_id <= 0 {
+            return Err(invalid("api_id", "must be a positive number"));
+        }
+        if config.response_timeout == Some(0) {
+            return Err(invalid("response_timeout", "must be a positive number"));
+        }
+        let tokens = clean_lines(config.tokens);
+        if tokens.is_empty() {
+            return Err(invalid(
+                "tokens",
+                "must have at least one token, the API can't be used without tokens",
+            ));
+        }
+        Ok(Self {
+            bots: clean_lines(config.bots),
+            tokens,
+            ..config
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FileConfig;
+
+    const CONFIG: &str = r#"
+api_id = 1234
+api_hash = "hash"
+port = 3939
+bots = ["@FirstBot", "  "]
+tokens = [" FirstToken "]
+
+[headers]
+X-Frame-Options = "DENY"
+"#;
+
+    #[test]
+    fn parses_the_config() {
+        let config = FileConfig::from_toml(CONFIG).unwrap();
+        assert_eq!(config.api_id, 1234);
+        assert_eq!(config.port, Some(3939));
+        assert_eq!(config.bots, ["@FirstBot"]);
+        assert_eq!(config.tokens, ["FirstToken"]);
+        assert_eq!(config.headers["X-Frame-Options"], "DENY");
+    }
+
+    #[test]
+    fn reports_the_invalid_field() {
+        let unknown = FileConfig::from_toml(&format!("unknown = 1\n{CONFIG}"))
+            .unwrap_err()
+            .to_string();
+        assert!(unknown.contains("unknown"), "{unknown}");
+        let port = FileConfig::from_toml(&CONFIG.replace("3939", "70000"))
+            .unwrap_err()
+            .to_string();
+        assert!(port.contains("port"), "{port}");
+    }
+}
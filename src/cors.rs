@@ -0,0 +1,116 @@
+// A simple API to ping telegram bots and returns if it's online or not.
+// Copyright (C) 2023  Awiteb <awitb@hotmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::collections::HashSet;
+
+use salvo::{
+    http::{HeaderValue, Method},
+    hyper::header,
+    prelude::*,
+};
+
+/// The default allowed methods of the CORS requests
+const DEFAULT_METHODS: &str = "GET, POST, OPTIONS";
+/// The default allowed headers of the CORS requests
+const DEFAULT_HEADERS: &str = "Authorization, Content-Type, X-Request-Id";
+/// The headers that the browser can read from the response
+const EXPOSE_HEADERS: &str = "Retry-After, X-Request-Id";
+/// How long the browser can cache the preflight response, in seconds
+const MAX_AGE: u32 = 600;
+
+/// CORS hoop, allows the browsers from the configured origins to call the API
+#[derive(Debug)]
+pub(crate) struct Cors {
+    /// The allowed origins, `None` means any origin
+    origins: Option<HashSet<String>>,
+    methods: HeaderValue,
+    headers: HeaderValue,
+}
+
+impl Cors {
+    /// Create new [`Cors`] from `TELEPINGBOT_CORS_ORIGINS`, `TELEPINGBOT_CORS_METHODS` and
+    /// `TELEPINGBOT_CORS_HEADERS` environment variables, `None` if there is no origins
+    pub(crate) fn from_env() -> crate::Result<Option<Self>> {
+        let Some(origins) = std::env::var("TELEPINGBOT_CORS_ORIGINS")
+            .ok()
+            .filter(|o| !o.trim().is_empty())
+        else {
+            return Ok(None);
+        };
+        let origins: HashSet<String> = origins
+            .split(',')
+            .map(|o| o.trim().trim_end_matches('/').to_owned())
+            .filter(|o| !o.is_empty())
+            .collect();
+        let header_value = |name: &str, default: &str| {
+            let value = std::env::var(name).unwrap_or_else(|_| default.to_owned());
+            HeaderValue::from_str(value.trim())
+                .map_err(|_| crate::Error::Config(format!("Invalid value for `{name}`: `{value}`")))
+        };
+
+        Ok(Some(Self {
+            origins: (!origins.contains("*")).then_some(origins),
+            methods: header_value("TELEPINGBOT_CORS_METHODS", DEFAULT_METHODS)?,
+            headers: header_value("TELEPINGBOT_CORS_HEADERS", DEFAULT_HEADERS)?,
+        }))
+    }
+
+    /// Returns if the origin is allowed
+    fn is_allowed(&self, origin: &str) -> bool {
+        self.origins
+            .as_ref()
+            .is_none_or(|origins| origins.contains(origin))
+    }
+}
+
+#[async_trait::async_trait]
+impl Handler for Cors {
+    async fn handle(
+        &self,
+        req: &mut Request,
+        _depot: &mut Depot,
+        res: &mut Response,
+        ctrl: &mut FlowCtrl,
+    ) {
+        let Some(origin) = req.headers().get(header::ORIGIN).cloned() else {
+            return;
+        };
+        let headers = res.headers_mut();
+        headers.insert(header::VARY, HeaderValue::from_static("Origin"));
+        if !origin.to_str().is_ok_and(|o| self.is_allowed(o)) {
+            log::debug!("CORS request from not allowed origin: {origin:?}");
+            return;
+        }
+        headers.insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+
+        if req.method() == Method::OPTIONS
+            && req
+                .headers()
+                .contains_key(header::ACCESS_CONTROL_REQUEST_METHOD)
+        {
+            headers.insert(header::ACCESS_CONTROL_ALLOW_METHODS, self.methods.clone());
+            headers.insert(header::ACCESS_CONTROL_ALLOW_HEADERS, self.headers.clone());
+            headers.insert(header::ACCESS_CONTROL_MAX_AGE, HeaderValue::from(MAX_AGE));
+            res.status_code(StatusCode::NO_CONTENT);
+            ctrl.skip_rest();
+        } else {
+            headers.insert(
+                header::ACCESS_CONTROL_EXPOSE_HEADERS,
+                HeaderValue::from_static(EXPOSE_HEADERS),
+            );
+        }
+    }
+}
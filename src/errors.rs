@@ -0,0 +1,164 @@
+// A simple API to ping telegram bots and returns if it's online or not.
+// Copyright (C) 2023  Awiteb <awitb@hotmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use grammers_client::SignInError;
+use grammers_mtsender::{AuthorizationError, InvocationError};
+
+/// The errors of the API
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// The username can't be resolved to a chat
+    #[error("Can't resolve the username `{0}`")]
+    Resolve(String),
+    /// The username is resolved to a chat that is not a bot, e.g. a user or a channel
+    #[error("`@{0}` is not a bot account")]
+    NotBot(String),
+    /// The chat to send the probe into is not in the account dialogs
+    #[error("Can't find the chat `{0}`, the account must be a member of it")]
+    ChatNotFound(i64),
+    /// Telegram limited us, must wait the given seconds before retrying
+    #[error("Flood wait, must wait {0} seconds")]
+    FloodWait(u32),
+    /// We blocked the bot, or the bot blocked us, e.g. `YOU_BLOCKED_USER`
+    #[error("The bot is blocked: {0}")]
+    Blocked(String),
+    /// Telegram restricted us from sending to the bot, e.g. `PEER_FLOOD`
+    #[error("Restricted from sending to the bot: {0}")]
+    Restricted(String),
+    /// Too many pings are waiting their turn, must wait the given seconds before retrying
+    #[error("Overloaded, too many pings are waiting")]
+    Overloaded(u32),
+    /// The ping took longer than the ping timeout
+    #[error("Timeout while pinging the bot")]
+    Timeout,
+    /// A login step took longer than the login timeout, e.g. telegram is unreachable
+    #[error("Timeout while {0}, telegram doesn't response")]
+    LoginTimeout(&'static str),
+    /// The telegram session is revoked or expired, e.g. `AUTH_KEY_UNREGISTERED`
+    #[error("The telegram session is revoked: {0}")]
+    SessionRevoked(String),
+    /// Telegram request error
+    #[error("Telegram request error: {0}")]
+    Telegram(InvocationError),
+    /// Can't connect to telegram
+    #[error("Can't connect to telegram: {0}")]
+    Connect(#[from] AuthorizationError),
+    /// Can't sign in to telegram
+    #[error("Can't sign in: {0}")]
+    SignIn(Box<SignInError>),
+    /// Can't load the session file, with its path
+    #[error("Can't load the session file `{0}`: {1}")]
+    Session(String, std::io::Error),
+    /// Can't deliver the webhook payload
+    #[error("Webhook error: {0}")]
+    Webhook(String),
+    /// The ping history database error
+    #[cfg(feature = "history")]
+    #[error("Database error: {0}")]
+    Database(#[from] rusqlite::Error),
+    /// Invalid configuration
+    #[error("{0}")]
+    Config(String),
+    #[error("Can't read the input: {0}")]
+    Prompt(#[from] promptly::ReadlineError),
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Task error: {0}")]
+    Task(#[from] tokio::task::JoinError),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl From<SignInError> for Error {
+    fn from(err: SignInError) -> Self {
+        Self::SignIn(Box::new(err))
+    }
+}
+
+impl Error {
+    /// Returns the send error, the blocked and the restricted errors are distinguished
+    /// from the other telegram errors
+    pub(crate) fn from_send_error(err: InvocationError) -> Self {
+        match err {
+            InvocationError::Rpc(ref rpc)
+                if ["YOU_BLOCKED_USER", "USER_IS_BLOCKED", "USER_BLOCKED"]
+                    .iter()
+                    .any(|name| rpc.is(name)) =>
+            {
+                Self::Blocked(rpc.name.clone())
+            }
+            InvocationError::Rpc(ref rpc)
+                if [
+                    "PEER_FLOOD",
+                    "USER_PRIVACY_RESTRICTED",
+                    "CHAT_WRITE_FORBIDDEN",
+                    "USER_RESTRICTED",
+                ]
+                .iter()
+                .any(|name| rpc.is(name)) =>
+            {
+                Self::Restricted(rpc.name.clone())
+            }
+            err => err.into(),
+        }
+    }
+
+    /// Whether the error is from the account itself not the bot, e.g. the account is limited
+    /// or banned, so all the pings will fail
+    pub(crate) fn is_account_error(&self) -> bool {
+        match self {
+            Self::FloodWait(_) | Self::SessionRevoked(_) => true,
+            Self::Restricted(name) => ["PEER_FLOOD", "USER_RESTRICTED"].contains(&name.as_str()),
+            _ => false,
+        }
+    }
+
+    /// Returns a short summary of the error without the internals, e.g. the telegram error
+    /// name `USERNAME_NOT_OCCUPIED`
+    pub(crate) fn detail(&self) -> String {
+        match self {
+            Self::Blocked(name) | Self::Restricted(name) | Self::SessionRevoked(name) => {
+                name.clone()
+            }
+            Self::Telegram(InvocationError::Rpc(rpc)) => rpc.name.clone(),
+            Self::Telegram(InvocationError::Dropped) => "REQUEST_DROPPED".to_owned(),
+            Self::Telegram(InvocationError::Read(_)) => "READ_ERROR".to_owned(),
+            Self::Resolve(_) => "USERNAME_NOT_RESOLVED".to_owned(),
+            Self::NotBot(_) => "NOT_A_BOT".to_owned(),
+            Self::ChatNotFound(_) => "CHAT_NOT_FOUND".to_owned(),
+            Self::FloodWait(_) => "FLOOD_WAIT".to_owned(),
+            Self::Overloaded(_) => "OVERLOADED".to_owned(),
+            Self::Timeout => "PING_TIMEOUT".to_owned(),
+            _ => "INTERNAL_ERROR".to_owned(),
+        }
+    }
+}
+
+impl From<InvocationError> for Error {
+    fn from(err: InvocationError) -> Self {
+        match err {
+            // e.g. `FLOOD_WAIT`, `FLOOD_PREMIUM_WAIT` and `SLOWMODE_WAIT`
+            InvocationError::Rpc(ref rpc) if rpc.is("*_WAIT") => {
+                Self::FloodWait(rpc.value.unwrap_or_default())
+            }
+            // e.g. `AUTH_KEY_UNREGISTERED`, `SESSION_REVOKED` and `USER_DEACTIVATED`
+            InvocationError::Rpc(ref rpc) if rpc.code == 401 => {
+                Self::SessionRevoked(rpc.name.clone())
+            }
+            err => Self::Telegram(err),
+        }
+    }
+}
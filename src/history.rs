@@ -0,0 +1,162 @@
+// A simple API to ping telegram bots and returns if it's online or not.
+// Copyright (C) 2023  Awiteb <awitb@hotmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::sync::Mutex;
+
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use rusqlite::{params, Connection};
+
+/// The max ping results returned by the history query
+const HISTORY_LIMIT: usize = 1000;
+
+/// The database migrations, each one runs once in order. The applied migrations count is
+/// stored in the `user_version` pragma, so only append new migrations here
+const MIGRATIONS: &[&str] = &["CREATE TABLE pings (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        bot TEXT NOT NULL,
+        checked_at INTEGER NOT NULL,
+        alive INTEGER NOT NULL,
+        latency_ms INTEGER
+    );
+    CREATE INDEX pings_bot_checked_at ON pings (bot, checked_at);"];
+
+/// A ping result in the history
+#[derive(Debug, serde::Serialize)]
+pub(crate) struct HistoryEntry {
+    pub alive: bool,
+    pub latency_ms: Option<u64>,
+    /// When the bot is pinged, in RFC 3339
+    pub checked_at: String,
+}
+
+/// The ping history, stored in a SQLite database
+#[derive(Debug)]
+pub(crate) struct History {
+    conn: Mutex<Connection>,
+}
+
+impl History {
+    /// Open the database and run the migrations
+    pub(crate) fn open(path: &str) -> crate::Result<Self> {
+        let mut conn = Connection::open(path)?;
+        migrate(&mut conn)?;
+        log::info!("The ping history is stored in `{path}`");
+        Ok(Self {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    /// Record a ping result of the bot, the username must be clean
+    pub(crate) fn record(
+        &self,
+        bot_username: &str,
+        checked_at: DateTime<Utc>,
+        alive: bool,
+        latency_ms: Option<u64>,
+    ) -> crate::Result<()> {
+        self.conn.lock().unwrap().execute(
+            "INSERT INTO pings (bot, checked_at, alive, latency_ms) VALUES (?1, ?2, ?3, ?4)",
+            params![
+                bot_username,
+                checked_at.timestamp_millis(),
+                alive,
+                latency_ms.map(|latency_ms| latency_ms as i64)
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Returns the newest ping results of the bot since the given time, oldest first
+    pub(crate) fn query(
+        &self,
+        bot_username: &str,
+        since: Option<DateTime<Utc>>,
+    ) -> crate::Result<Vec<HistoryEntry>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT alive, latency_ms, checked_at FROM pings
+             WHERE bot = ?1 AND checked_at >= ?2
+             ORDER BY checked_at DESC LIMIT ?3",
+        )?;
+        let mut entries = stmt
+            .query_map(
+                params![
+                    bot_username,
+                    since.map_or(0, |since| since.timestamp_millis()),
+                    HISTORY_LIMIT as i64
+                ],
+                |row| {
+                    Ok(HistoryEntry {
+                        alive: row.get(0)?,
+                        latency_ms: row.get::<_, Option<i64>>(1)?.map(|l| l as u64),
+                        checked_at: Utc
+                            .timestamp_millis_opt(row.get(2)?)
+                            .single()
+                            .unwrap_or_default()
+                            .to_rfc3339(),
+                    })
+                },
+            )?
+            .collect::<Result<Vec<_>, _>>()?;
+        entries.reverse();
+        Ok(entries)
+    }
+
+    /// Returns the alive pings count and all the pings count of the bot since the given time
+    pub(crate) fn uptime(
+        &self,
+        bot_username: &str,
+        since: DateTime<Utc>,
+    ) -> crate::Result<(u64, u64)> {
+        let (alive, samples): (i64, i64) = self.conn.lock().unwrap().query_row(
+            "SELECT COALESCE(SUM(alive), 0), COUNT(*) FROM pings
+             WHERE bot = ?1 AND checked_at >= ?2",
+            params![bot_username, since.timestamp_millis()],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        Ok((alive as u64, samples as u64))
+    }
+}
+
+/// Parse the time window, a positive number with `m`, `h` or `d` unit, e.g. `1h`, `24h` and `7d`
+pub(crate) fn parse_window(window: &str) -> Option<Duration> {
+    let (unit_index, _) = window.char_indices().last()?;
+    let (number, unit) = window.split_at(unit_index);
+    let number: i64 = number.parse().ok().filter(|&number| number > 0)?;
+    let unit_seconds = match unit {
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return None,
+    };
+    number
+        .checked_mul(unit_seconds)
+        .and_then(|seconds| seconds.checked_mul(1000))
+        .map(Duration::milliseconds)
+}
+
+/// Run the migrations that are not applied yet
+fn migrate(conn: &mut Connection) -> crate::Result<()> {
+    let applied: i64 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+    for (version, migration) in MIGRATIONS.iter().enumerate().skip(applied as usize) {
+        log::info!("Running the database migration {}", version + 1);
+        let tx = conn.transaction()?;
+        tx.execute_batch(migration)?;
+        tx.pragma_update(None, "user_version", version as i64 + 1)?;
+        tx.commit()?;
+    }
+    Ok(())
+}
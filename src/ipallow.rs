@@ -0,0 +1,160 @@
+// A simple API to ping telegram bots and returns if it's online or not.
+// Copyright (C) 2023  Awiteb <awitb@hotmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::net::IpAddr;
+
+use ipnet::IpNet;
+
+/// The allowed client IPs, the `X-Forwarded-For` header is only trusted from the trusted proxies
+#[derive(Debug)]
+pub(crate) struct IpAllowlist {
+    allowed: Vec<IpNet>,
+    trusted_proxies: Vec<IpNet>,
+}
+
+/// Parse the network in the CIDR notation, e.g. `10.0.0.0/8`, a single IP is a `/32` or `/128`
+fn parse_net(net: &str) -> Result<IpNet, String> {
+    if net.contains('/') {
+        net.parse()
+            .map_err(|_| format!("Invalid IP network `{net}`"))
+    } else {
+        net.parse::<IpAddr>()
+            .map(IpNet::from)
+            .map_err(|_| format!("Invalid IP address `{net}`"))
+    }
+}
+
+/// Returns `true` if the IP is in one of the networks, the IPv4-mapped IPv6 addresses are
+/// compared as IPv4
+fn contains(nets: &[IpNet], ip: IpAddr) -> bool {
+    let ip = match ip {
+        IpAddr::V6(ip) => ip.to_ipv4_mapped().map_or(IpAddr::V6(ip), IpAddr::V4),
+        ip => ip,
+    };
+    nets.iter().any(|net| net.contains(&ip))
+}
+
+/// Parse the comma separated networks
+fn parse_nets(name: &str, nets: &str) -> crate::Result<Vec<IpNet>> {
+    nets.split(',')
+        .map(str::trim)
+        .filter(|net| !net.is_empty())
+        .map(|net| {
+            parse_net(net)
+                .map_err(|err| crate::Error::Config(format!("Invalid value for `{name}`: {err}")))
+        })
+        .collect()
+}
+
+impl IpAllowlist {
+    /// Create new [`IpAllowlist`] from `TELEPINGBOT_ALLOWED_IPS` and `TELEPINGBOT_TRUSTED_PROXIES`
+    /// environment variables, `None` if there is no allowed IPs
+    pub(crate) fn from_env() -> crate::Result<Option<Self>> {
+        let allowed = parse_nets(
+            "TELEPINGBOT_ALLOWED_IPS",
+            &std::env::var("TELEPINGBOT_ALLOWED_IPS").unwrap_or_default(),
+        )?;
+        if allowed.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Self {
+            allowed,
+            trusted_proxies: parse_nets(
+                "TELEPINGBOT_TRUSTED_PROXIES",
+                &std::env::var("TELEPINGBOT_TRUSTED_PROXIES").unwrap_or_default(),
+            )?,
+        }))
+    }
+
+    /// Returns the client IP, if the remote is a trusted proxy the client is the last
+    /// untrusted IP in `X-Forwarded-For`, the proxies append to it
+    pub(crate) fn client_ip(&self, remote: IpAddr, forwarded_for: Option<&str>) -> IpAddr {
+        let is_trusted = |ip: IpAddr| contains(&self.trusted_proxies, ip);
+        if !is_trusted(remote) {
+            return remote;
+        }
+        let mut client = remote;
+        for ip in forwarded_for.unwrap_or_default().rsplit(',') {
+            match ip.trim().parse() {
+                Ok(ip) if is_trusted(client) => client = ip,
+                _ => break,
+            }
+        }
+        client
+    }
+
+    /// Returns `true` if the client IP is allowed
+    pub(crate) fn is_allowed(&self, ip: IpAddr) -> bool {
+        contains(&self.allowed, ip)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nets(nets: &str) -> Vec<IpNet> {
+        parse_nets("TELEPINGBOT_ALLOWED_IPS", nets).unwrap()
+    }
+
+    fn ip(ip: &str) -> IpAddr {
+        ip.parse().unwrap()
+    }
+
+    #[test]
+    fn contains_the_prefixes() {
+        let any = nets("0.0.0.0/0, ::/0");
+        assert!(contains(&any, ip("203.0.113.7")));
+        assert!(contains(&any, ip("2001:db8::1")));
+
+        let single = nets("10.0.0.1/32, 2001:db8::1/128");
+        assert!(contains(&single, ip("10.0.0.1")));
+        assert!(!contains(&single, ip("10.0.0.2")));
+        assert!(contains(&single, ip("2001:db8::1")));
+        assert!(!contains(&single, ip("2001:db8::2")));
+        // A single IP without a prefix
+        assert_eq!(nets("10.0.0.1, 2001:db8::1"), single);
+
+        let private = nets("10.0.0.0/8");
+        assert!(contains(&private, ip("10.20.30.40")));
+        assert!(!contains(&private, ip("11.0.0.1")));
+    }
+
+    #[test]
+    fn contains_the_ipv4_mapped_ipv6() {
+        let private = nets("10.0.0.0/8");
+        assert!(contains(&private, ip("::ffff:10.1.2.3")));
+        assert!(!contains(&private, ip("::ffff:11.1.2.3")));
+        // The IPv4 networks never contain the other IPv6 addresses
+        assert!(!contains(&nets("0.0.0.0/0"), ip("2001:db8::1")));
+    }
+
+    #[test]
+    fn parse_invalid_nets() {
+        for net in [
+            "10.0.0.0/33",
+            "2001:db8::/129",
+            "10.0.0",
+            "10.0.0.0/",
+            "bot",
+        ] {
+            assert!(
+                parse_nets("TELEPINGBOT_ALLOWED_IPS", net).is_err(),
+                "`{net}` is valid"
+            );
+        }
+    }
+}
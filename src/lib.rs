@@ -0,0 +1,1331 @@
+// A simple API to ping telegram bots and returns if it's online or not.
+// Copyright (C) 2023  Awiteb <awitb@hotmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+//! A simple API to ping telegram bots, the API is served by [`run`] and the pinger can be
+//! embedded in another service with [`Pinger`].
+//!
+//! ```no_run
+//! # async fn example() -> telepingbot::Result<()> {
+//! let pinger = telepingbot::Pinger::connect("telebotping.session", "hash".to_owned(), 1234)
+//!     .await?;
+//! match pinger.ping("@SomeTestBot").await? {
+//!     telepingbot::PingOutcome::Alive { latency_ms, .. } => println!("Alive in {latency_ms}ms"),
+//!     telepingbot::PingOutcome::NoResponse => println!("No response"),
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    collections::HashMap,
+    env, fs,
+    net::{SocketAddr, ToSocketAddrs},
+    path::{Path, PathBuf},
+    sync::{atomic::Ordering, Arc, Mutex},
+    time::Duration,
+};
+
+use salvo::Listener;
+use tokio::sync::Notify;
+use tokio_util::sync::CancellationToken;
+
+pub use errors::{Error, Result};
+pub use pinger::{PingOutcome, Pinger};
+
+mod accesslog;
+mod api;
+mod config;
+mod cors;
+mod errors;
+#[cfg(feature = "history")]
+mod history;
+mod ipallow;
+mod logger;
+mod metrics;
+mod openapi;
+mod pinger;
+mod ratelimit;
+mod superbot;
+mod validate;
+mod webhook;
+
+/// The status of a ping in the list
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum PingStatus {
+    /// The bot response to the ping
+    Alive,
+    /// The bot doesn't response in the response timeout, or it isn't pinged
+    NoResponse,
+    /// The bot is pinged and still in the response timeout, its status is not known yet
+    Pending,
+}
+
+/// The pings of a pinger, shared with its telegram updates handlers. Each pinger has its own
+/// pings, so the pingers in one process don't collide
+#[derive(Debug, Default)]
+pub(crate) struct Pings {
+    /// The pinged bots by their telegram id. The lock is never held across an `.await` or
+    /// while locking another lock, so the requests, the updates handler and the sweeper
+    /// can't deadlock each other
+    pub(crate) pinged: Mutex<HashMap<u64, PingedBot>>,
+    /// When each bot sent its last message by its telegram id, in seconds. Recorded for all the
+    /// bots, for the passive pings
+    pub(crate) last_seen: Mutex<HashMap<u64, i64>>,
+}
+
+impl Pings {
+    /// Record a new message of the bot, sent in the given timestamp in seconds
+    pub(crate) fn seen(&self, telegram_id: u64, sent_in: i64) {
+        self.last_seen
+            .lock()
+            .unwrap()
+            .entry(telegram_id)
+            .and_modify(|seen| *seen = (*seen).max(sent_in))
+            .or_insert(sent_in);
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct PingedBot {
+    /// The ping timestamp, in milliseconds
+    ping_in: i64,
+    is_response: bool,
+    /// The response timestamp, in milliseconds
+    res_in: Option<i64>,
+    /// Notified when the bot response
+    notify: Arc<Notify>,
+    /// The pattern the response must match, any message is a response if it's `None`
+    expect: Option<regex::Regex>,
+}
+
+#[async_trait::async_trait]
+pub(crate) trait PingList {
+    fn clear_outdead(&self, response_timeout: u64);
+    /// Add a new ping, the response must match the `expect` pattern if it's set
+    fn add_new(&self, telegram_id: u64, expect: Option<regex::Regex>);
+    /// Returns the ping status of the bot, the ping is pending until the response timeout
+    /// elapses
+    fn check(&self, telegram_id: u64, response_timeout: u64) -> PingStatus;
+    /// Record a new response from the bot, `sent_in` is the message timestamp in seconds
+    /// and `text` is the message text
+    fn new_res(&self, telegram_id: u64, sent_in: i64, text: &str);
+    /// Wait until the bot response or the timeout elapses, returns the response
+    /// latency in milliseconds if the bot response
+    async fn wait_for_response(&self, telegram_id: u64, response_timeout: u64) -> Option<u64>;
+    /// Returns a copy of the current pings with their telegram ids
+    fn snapshot(&self) -> Vec<(u64, PingedBot)>;
+}
+
+#[async_trait::async_trait]
+impl PingList for Mutex<HashMap<u64, PingedBot>> {
+    fn clear_outdead(&self, response_timeout: u64) {
+        log::debug!("Clear the dead pings");
+        // Keep the pings alive at least for the response timeout, so a long
+        // timeout doesn't remove the ping before checking it
+        let dead_time =
+            chrono::Utc::now().timestamp_millis() - (60 + response_timeout as i64) * 1000;
+        self.lock().unwrap().retain(|_, b| b.ping_in > dead_time);
+    }
+
+    fn add_new(&self, telegram_id: u64, expect: Option<regex::Regex>) {
+        log::debug!("Adding new bot to the list: {telegram_id}");
+        self.lock()
+            .unwrap()
+            .entry(telegram_id)
+            // Keep the same notify, so the waiters of the old ping are notified too
+            .and_modify(|bot| bot.new_ping(expect.clone()))
+            .or_insert_with(|| PingedBot::new(expect));
+    }
+
+    fn check(&self, telegram_id: u64, response_timeout: u64) -> PingStatus {
+        log::debug!("Checking the {telegram_id} if is response");
+        let result = self
+            .lock()
+            .unwrap()
+            .get(&telegram_id)
+            .map_or(PingStatus::NoResponse, |b| b.status(response_timeout));
+        log::debug!("Response status: {result:?}");
+        result
+    }
+
+    fn new_res(&self, telegram_id: u64, sent_in: i64, text: &str) {
+        if let Some(bot) = self.lock().unwrap().get_mut(&telegram_id) {
+            log::debug!("New res from: {telegram_id}");
+            // Telegram dates are in seconds, so compare with the ping second. The older
+            // messages (e.g. queued before the ping) are not a response to the ping
+            if sent_in < bot.ping_in / 1000 {
+                log::debug!("Ignoring an old message from {telegram_id}");
+                return;
+            }
+            // e.g. a broadcast of a chatty bot is not a response to the ping
+            if matches!(&bot.expect, Some(expect) if !expect.is_match(text)) {
+                log::debug!(
+                    "Ignoring a message from {telegram_id} that doesn't match the expected"
+                );
+                return;
+            }
+            bot.notify.notify_waiters();
+            bot.new_res();
+        }
+    }
+
+    fn snapshot(&self) -> Vec<(u64, PingedBot)> {
+        self.lock()
+            .unwrap()
+            .iter()
+            .map(|(telegram_id, bot)| (*telegram_id, bot.clone()))
+            .collect()
+    }
+
+    async fn wait_for_response(&self, telegram_id: u64, response_timeout: u64) -> Option<u64> {
+        log::debug!("Waiting the {telegram_id} response");
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(response_timeout);
+        let notify = self
+            .lock()
+            .unwrap()
+            .get(&telegram_id)
+            .map(|b| Arc::clone(&b.notify))?;
+
+        loop {
+            let notified = notify.notified();
+            tokio::pin!(notified);
+            // Register the waiter before checking, so a response between the
+            // check and the wait is not missed
+            notified.as_mut().enable();
+            if self.check(telegram_id, response_timeout) == PingStatus::Alive {
+                return self
+                    .lock()
+                    .unwrap()
+                    .get(&telegram_id)
+                    .and_then(PingedBot::latency);
+            }
+            if tokio::time::timeout_at(deadline, notified).await.is_err() {
+                log::debug!("Timeout waiting the {telegram_id} response");
+                return None;
+            }
+        }
+    }
+}
+
+impl PingedBot {
+    pub(crate) fn new(expect: Option<regex::Regex>) -> Self {
+        Self {
+            ping_in: chrono::Utc::now().timestamp_millis(),
+            is_response: false,
+            res_in: None,
+            notify: Arc::new(Notify::new()),
+            expect,
+        }
+    }
+
+    /// Reset the ping, as a new ping sent to the bot
+    pub(crate) fn new_ping(&mut self, expect: Option<regex::Regex>) {
+        self.ping_in = chrono::Utc::now().timestamp_millis();
+        self.is_response = false;
+        self.res_in = None;
+        self.expect = expect;
+    }
+
+    pub(crate) fn new_res(&mut self) {
+        if !self.is_response {
+            self.is_response = true;
+            self.res_in = Some(chrono::Utc::now().timestamp_millis());
+        }
+    }
+
+    /// Returns the ping status, the ping is pending until the response timeout (in seconds)
+    /// elapses
+    pub(crate) fn status(&self, response_timeout: u64) -> PingStatus {
+        let age = chrono::Utc::now().timestamp_millis() - self.ping_in;
+        if self.is_response {
+            PingStatus::Alive
+        } else if age < response_timeout as i64 * 1000 {
+            PingStatus::Pending
+        } else {
+            PingStatus::NoResponse
+        }
+    }
+
+    /// Returns the response latency in milliseconds, `None` if there is no response
+    pub(crate) fn latency(&self) -> Option<u64> {
+        self.res_in
+            .map(|res_in| (res_in - self.ping_in).max(0) as u64)
+    }
+}
+
+/// The default path of the bots file
+const BOTS_FILE: &str = "bots.txt";
+/// The default path of the tokens file
+const TOKENS_FILE: &str = "tokens.txt";
+/// The default path of the telegram session file
+const SESSION_FILE: &str = "telebotping.session";
+/// The default time to wait the bot response, in seconds
+const DEFAULT_RESPONSE_TIMEOUT: u64 = 2;
+/// The default interval between the monitor probes, in seconds
+const DEFAULT_MONITOR_INTERVAL: u64 = 60;
+/// The default interval between saving the bots status to the status file, in seconds
+const DEFAULT_STATUS_SAVE_INTERVAL: u64 = 60;
+/// The default interval between the dead pings sweeps, in seconds
+const DEFAULT_SWEEP_INTERVAL: u64 = 60;
+/// The default extra time of the ping timeout over the response timeout, in seconds
+const DEFAULT_PING_TIMEOUT_MARGIN: u64 = 3;
+/// The default time to wait between the ping attempts, in seconds
+const DEFAULT_RETRY_DELAY: u64 = 1;
+/// The default window of the passive pings, the bot is alive if it sent a message in it, in
+/// seconds
+const DEFAULT_PASSIVE_WINDOW: u64 = 300;
+/// The default account failures in a row to report the telegram client as not usable
+const DEFAULT_UNUSABLE_AFTER: usize = 5;
+/// The default maximum bots of a batch ping
+const DEFAULT_BATCH_MAX_BOTS: usize = 100;
+/// The default maximum body size of a batch ping, in bytes
+const DEFAULT_BATCH_MAX_BODY: usize = 64 * 1024;
+/// The maximum time to wait the server and telegram to shut down, in seconds
+const SHUTDOWN_TIMEOUT: u64 = 10;
+
+/// The TLS configuration of the server
+#[cfg(feature = "tls")]
+type TlsConfig = salvo::conn::rustls::RustlsConfig;
+/// The TLS is not supported without the `tls` feature
+#[cfg(not(feature = "tls"))]
+type TlsConfig = std::convert::Infallible;
+
+/// Parse a list file content, one entry per line. The empty lines and the lines
+/// starting with `#` are skipped, and the inline comments (`#` after a whitespace) are removed
+fn parse_list(content: &str) -> Vec<String> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let line = line.trim();
+            let entry = line
+                .char_indices()
+                .find(|&(idx, c)| {
+                    c == '#' && (idx == 0 || line[..idx].ends_with(char::is_whitespace))
+                })
+                .map_or(line, |(idx, _)| &line[..idx])
+                .trim();
+            (!entry.is_empty()).then(|| entry.to_owned())
+        })
+        .collect()
+}
+
+/// Read the bots and tokens files
+fn read_lists() -> Result<(Vec<String>, Vec<String>)> {
+    if let Some(path) = config::config_path() {
+        let config = config::FileConfig::load(&path)?;
+        return Ok((config.bots, config.tokens));
+    }
+    let bots = read_list(
+        &env_path("TELEPINGBOT_BOTS_FILE", BOTS_FILE),
+        "one `@username` per line",
+    )?;
+    let tokens_path = env_path("TELEPINGBOT_TOKENS_FILE", TOKENS_FILE);
+    let tokens = read_list(&tokens_path, "one token per line")?;
+    if tokens.is_empty() {
+        return Err(Error::Config(format!(
+            "There is no tokens in `{tokens_path}`, the API can't be used without tokens. Add one \
+             token per line"
+        )));
+    }
+    Ok((bots, tokens))
+}
+
+/// Read and merge comma separated list files, a path can be a directory of list files. The
+/// duplicated entries are removed, the first one is kept
+fn read_list(paths: &str, create_hint: &str) -> Result<Vec<String>> {
+    let mut entries: Vec<String> = Vec::new();
+    for path in paths.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        for entry in read_list_path(path, create_hint)? {
+            if !entries.contains(&entry) {
+                entries.push(entry);
+            }
+        }
+    }
+    Ok(entries)
+}
+
+/// Read and parse a list file, or all the files in a directory sorted by their names. The
+/// missing file error has how to create it
+fn read_list_path(path: &str, create_hint: &str) -> Result<Vec<String>> {
+    let read_error = |err: std::io::Error| match err.kind() {
+        std::io::ErrorKind::NotFound => {
+            Error::Config(format!("`{path}` not found, create it with {create_hint}"))
+        }
+        _ => Error::Config(format!("Faild to read `{path}`: {err}")),
+    };
+    if !Path::new(path).is_dir() {
+        return fs::read_to_string(path)
+            .map(|content| parse_list(&content))
+            .map_err(read_error);
+    }
+
+    let mut entries = Vec::new();
+    for file in list_files(Path::new(path)).map_err(read_error)? {
+        let content = fs::read_to_string(&file)
+            .map_err(|err| Error::Config(format!("Faild to read `{}`: {err}", file.display())))?;
+        entries.extend(parse_list(&content));
+    }
+    Ok(entries)
+}
+
+/// Returns the files in the directory sorted by their names
+fn list_files(dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let mut files = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|file| file.is_file())
+        .collect::<Vec<_>>();
+    files.sort();
+    Ok(files)
+}
+
+/// Print the `--check` report, returns whether all the checks passed
+fn print_check_report(
+    app_state: &api::AppState,
+    accounts: usize,
+    authorized: bool,
+    resolve_errors: &[String],
+) -> bool {
+    let status = |passed: bool| if passed { "ok  " } else { "FAIL" };
+    let access = app_state.access.read().unwrap();
+    println!(
+        "ok   config: {} bots, {} tokens",
+        access.bots.len(),
+        access.tokens.len()
+    );
+    if accounts == 0 {
+        println!("ok   telegram: the mock mode, no telegram connection is made");
+    } else {
+        let state = if authorized {
+            "authorized"
+        } else {
+            "not authorized"
+        };
+        println!(
+            "{} telegram: {accounts} accounts {state}",
+            status(authorized)
+        );
+    }
+    println!(
+        "{} bots: {} of {} resolved",
+        status(resolve_errors.is_empty()),
+        access.bots.len().saturating_sub(resolve_errors.len()),
+        access.bots.len()
+    );
+    resolve_errors.iter().for_each(|err| println!("     {err}"));
+    authorized && resolve_errors.is_empty()
+}
+
+/// Returns the tokens files to persist the tokens of the admin endpoints, all the tokens files
+/// of the tokens paths. Empty if the first tokens path is not a file, or with the config file
+fn tokens_files(config_file: bool) -> Vec<PathBuf> {
+    let paths = env_path("TELEPINGBOT_TOKENS_FILE", TOKENS_FILE);
+    let mut paths = paths
+        .split(',')
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+        .map(Path::new)
+        .peekable();
+    if config_file || !paths.peek().is_some_and(|path| path.is_file()) {
+        return Vec::new();
+    }
+    paths
+        .flat_map(|path| {
+            if path.is_dir() {
+                list_files(path).unwrap_or_default()
+            } else {
+                vec![path.to_owned()]
+            }
+        })
+        .collect()
+}
+
+/// Returns the path from the environment variable, or the default path
+fn env_path(name: &str, default: &str) -> String {
+    env::var(name)
+        .ok()
+        .filter(|path| !path.is_empty())
+        .unwrap_or_else(|| default.to_owned())
+}
+
+/// Returns `true` if the environment variable is set to `1`
+fn env_flag(name: &str) -> bool {
+    env::var(name).is_ok_and(|value| value == "1")
+}
+
+/// Returns the errors of the invalid bot usernames and options, each line starts with the bot
+/// username
+fn bots_errors(bots: &[String]) -> Vec<String> {
+    bots.iter()
+        .filter_map(|line| {
+            let line = line.trim();
+            let (b, probe) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            match b.strip_prefix('@') {
+                None => Some(format!("Invalid bot username `{b}`: must starts with `@`")),
+                Some(username) => api::validate_username(username)
+                    .err()
+                    .map(|rule| format!("Invalid bot username `{b}`: {rule}"))
+                    .or_else(|| {
+                        api::split_options(probe)
+                            .0
+                            .error()
+                            .map(|err| format!("Invalid options of `{b}`: {err}"))
+                    }),
+            }
+        })
+        .collect()
+}
+
+/// Parse the `Name: value` headers separated by `;`
+fn parse_headers(headers: &str) -> Vec<(String, String)> {
+    headers
+        .split(';')
+        .filter(|header| !header.trim().is_empty())
+        .map(|header| {
+            let (name, value) = header.split_once(':').unwrap_or((header, ""));
+            (name.trim().to_owned(), value.trim().to_owned())
+        })
+        .collect()
+}
+
+/// Reload the bots and tokens files, the old lists are kept if the new files are invalid
+#[cfg(unix)]
+fn reload_lists(app_state: &api::AppState) {
+    log::info!("Reloading the bots and tokens files");
+    match read_lists() {
+        Ok((bots, tokens)) => {
+            let bots_errors = bots_errors(&bots);
+            if bots_errors.is_empty() {
+                app_state.reload(bots, tokens);
+                log::info!("The bots and tokens files are reloaded");
+            } else {
+                bots_errors.iter().for_each(|e| log::error!("{e}"));
+                log::error!("Invalid bots file, keeping the old bots and tokens");
+            }
+        }
+        Err(err) => {
+            log::error!("Faild to read the bots and tokens files, keeping the old ones: {err}")
+        }
+    }
+}
+
+/// Reload the bots and tokens files on each `SIGHUP` signal
+#[cfg(unix)]
+async fn reload_on_hangup(app_state: Arc<api::AppState>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    match signal(SignalKind::hangup()) {
+        Ok(mut hangup) => {
+            while hangup.recv().await.is_some() {
+                reload_lists(&app_state);
+            }
+        }
+        Err(err) => log::error!("Faild to listen to SIGHUP signal: {err}"),
+    }
+}
+
+/// Cycle the log level when `SIGUSR1` is received
+#[cfg(unix)]
+async fn cycle_level_on_usr1() {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    match signal(SignalKind::user_defined1()) {
+        Ok(mut usr1) => {
+            while usr1.recv().await.is_some() {
+                let level = logger::cycle_level();
+                log::info!("The log level is changed to `{level}`");
+            }
+        }
+        Err(err) => log::error!("Faild to listen to SIGUSR1 signal: {err}"),
+    }
+}
+
+/// Returns the environment variable value, fallback to the deprecated name if it's not set
+fn env_with_alias(name: &str, deprecated: &str) -> Option<String> {
+    env::var(name).ok().or_else(|| {
+        let value = env::var(deprecated).ok();
+        if value.is_some() {
+            log::warn!("`{deprecated}` environment variable is deprecated, use `{name}` instead");
+        }
+        value
+    })
+}
+
+/// Returns the address to listen on from the given host and port, or `TELEPINGBOT_HOST`
+/// and `TELEPINGBOT_PORT`, the host can be an IPv4, IPv6 (e.g. `::1` or `[::1]`) or a domain
+fn listen_addr(host: Option<String>, port: Option<u16>) -> Result<SocketAddr> {
+    let host = host
+        .or_else(|| env_with_alias("TELEPINGBOT_HOST", "TELEOINGBOT_HOST"))
+        .ok_or_else(|| {
+            Error::Config("`TELEPINGBOT_HOST` environment variable must be set".to_owned())
+        })?;
+    let port: u16 = match port {
+        Some(port) => port,
+        None => env_with_alias("TELEPINGBOT_PORT", "TELEOINGBOT_PORT")
+            .ok_or_else(|| {
+                Error::Config("`TELEPINGBOT_PORT` environment variable must be set".to_owned())
+            })?
+            .trim()
+            .parse()
+            .map_err(|_| {
+                Error::Config(
+                    "Invalid value for `TELEPINGBOT_PORT` must be a port number (0-65535)"
+                        .to_owned(),
+                )
+            })?,
+    };
+    let host = host.trim().trim_start_matches('[').trim_end_matches(']');
+
+    (host, port)
+        .to_socket_addrs()
+        .ok()
+        .and_then(|mut addrs| addrs.next())
+        .ok_or_else(|| {
+            Error::Config(format!(
+                "Invalid value for `TELEPINGBOT_HOST`: can't resolve `{host}`"
+            ))
+        })
+}
+
+/// Remove the dead pings every interval, until the shutdown. The pings are kept for the longest
+/// response timeout of the bots
+async fn sweep_outdead(
+    interval: Duration,
+    app_state: Arc<api::AppState>,
+    shutdown: CancellationToken,
+) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        tokio::select! {
+            _ = shutdown.cancelled() => break,
+            _ = ticker.tick() => app_state
+                .pinger
+                .pings()
+                .pinged
+                .clear_outdead(app_state.max_response_timeout()),
+        }
+    }
+}
+
+/// Save the bots status to the status file every interval, until the shutdown
+async fn save_status_every(
+    status_file: PathBuf,
+    interval: Duration,
+    app_state: Arc<api::AppState>,
+    shutdown: CancellationToken,
+) {
+    let mut ticker = tokio::time::interval(interval);
+    // The first tick is immediate, there is nothing new to save
+    ticker.tick().await;
+    loop {
+        tokio::select! {
+            _ = shutdown.cancelled() => break,
+            _ = ticker.tick() => {
+                if let Err(err) = app_state.save_status(&status_file) {
+                    log::error!("Faild to save the status file: {err}");
+                }
+            }
+        }
+    }
+}
+
+/// Returns the TLS configuration from `TELEPINGBOT_TLS_CERT` and `TELEPINGBOT_TLS_KEY`
+/// environment variables, `None` if both are not set
+fn tls_config() -> Result<Option<TlsConfig>> {
+    let cert = env::var("TELEPINGBOT_TLS_CERT")
+        .ok()
+        .filter(|c| !c.is_empty());
+    let key = env::var("TELEPINGBOT_TLS_KEY")
+        .ok()
+        .filter(|k| !k.is_empty());
+    let client_ca = client_ca();
+    match (cert, key) {
+        (Some(cert), Some(key)) => load_tls(&cert, &key, client_ca.as_deref()).map(Some),
+        (None, None) if client_ca.is_some() => Err(Error::Config(
+            "`TELEPINGBOT_TLS_CLIENT_CA` requires `TELEPINGBOT_TLS_CERT` and `TELEPINGBOT_TLS_KEY`"
+                .to_owned(),
+        )),
+        (None, None) => Ok(None),
+        (Some(_), None) => Err(Error::Config(
+            "`TELEPINGBOT_TLS_KEY` environment variable is required with `TELEPINGBOT_TLS_CERT`"
+                .to_owned(),
+        )),
+        (None, Some(_)) => Err(Error::Config(
+            "`TELEPINGBOT_TLS_CERT` environment variable is required with `TELEPINGBOT_TLS_KEY`"
+                .to_owned(),
+        )),
+    }
+}
+
+/// Returns the CA PEM file of the client certificates, the clients must have a certificate
+/// signed by it if it's set
+fn client_ca() -> Option<String> {
+    env::var("TELEPINGBOT_TLS_CLIENT_CA")
+        .ok()
+        .filter(|ca| !ca.is_empty())
+}
+
+/// Load the TLS certificate and key PEM files, the client certificates are required and
+/// verified with the client CA PEM file if it's set
+#[cfg(feature = "tls")]
+fn load_tls(cert: &str, key: &str, client_ca: Option<&str>) -> Result<TlsConfig> {
+    use salvo::conn::rustls::{Keycert, ServerConfig};
+
+    let keycert = Keycert::new()
+        .cert_from_path(cert)
+        .map_err(|err| Error::Config(format!("Faild to read the TLS certificate `{cert}`: {err}")))?
+        .key_from_path(key)
+        .map_err(|err| Error::Config(format!("Faild to read the TLS key `{key}`: {err}")))?;
+    let config = TlsConfig::new(keycert);
+    let config = match client_ca {
+        Some(client_ca) => config.client_auth_required_path(client_ca).map_err(|err| {
+            Error::Config(format!(
+                "Faild to read the TLS client CA `{client_ca}`: {err}"
+            ))
+        })?,
+        None => config,
+    };
+    // Build the config once, so the invalid files are reported at the startup
+    TryInto::<ServerConfig>::try_into(config.clone())
+        .map_err(|err| Error::Config(format!("Invalid TLS certificate or key: {err}")))?;
+    Ok(config)
+}
+
+/// Load the TLS certificate and key PEM files, the client certificates are required and
+/// verified with the client CA PEM file if it's set
+#[cfg(not(feature = "tls"))]
+fn load_tls(_cert: &str, _key: &str, _client_ca: Option<&str>) -> Result<TlsConfig> {
+    Err(Error::Config(
+        "TLS is not supported, build the API with the `tls` feature".to_owned(),
+    ))
+}
+
+/// Serve the API until the shutdown
+async fn serve(
+    acceptor: impl salvo::conn::Acceptor + Send,
+    service: salvo::Service,
+    shutdown: CancellationToken,
+) {
+    salvo::Server::new(acceptor)
+        .serve_with_graceful_shutdown(
+            service,
+            shutdown.cancelled_owned(),
+            Some(Duration::from_secs(SHUTDOWN_TIMEOUT)),
+        )
+        .await;
+    log::info!("The server is stopped");
+}
+
+/// The validated startup configuration, from the config file and the environment variables
+struct StartupConfig {
+    bots: Vec<String>,
+    tokens: Vec<String>,
+    /// The tokens files to persist the added and revoked tokens in, empty with the config file
+    tokens_files: Vec<PathBuf>,
+    listen_addr: SocketAddr,
+    tls_config: Option<TlsConfig>,
+    client_cert_auth: bool,
+    access_log: accesslog::AccessLog,
+    server_headers: api::ServerHeaders,
+    cors: Option<cors::Cors>,
+    ip_allowlist: Option<ipallow::IpAllowlist>,
+    webhook: Option<webhook::Webhook>,
+    sessions_dir: Option<String>,
+    /// The api hash and id, `None` in the mock mode
+    api_credentials: Option<(String, i32)>,
+    login_config: superbot::LoginConfig,
+    response_timeout: u64,
+    ping_timeout: u64,
+    rate_limit: Option<u32>,
+    sweep_interval: u64,
+    status_save_interval: u64,
+    monitor_interval: Option<u64>,
+    monitor_jitter: u64,
+    ping_attempts: u32,
+    retry_delay: u64,
+    startup_grace: u64,
+    max_concurrency: Option<usize>,
+    max_queue: Option<usize>,
+    batch_max_bots: usize,
+    batch_max_body: usize,
+    unusable_after: usize,
+    exit_on_revoked: bool,
+    strict_http: bool,
+    cooldown: u64,
+    seen_within: u64,
+    passive_window: u64,
+    #[cfg(feature = "history")]
+    history: Option<history::History>,
+}
+
+/// Validate the startup configuration before connecting to telegram, returns the problems if it's
+/// invalid
+fn startup_config() -> std::result::Result<StartupConfig, validate::Problems> {
+    let mut problems = validate::Problems::default();
+    let config_path = config::config_path();
+    let file_config = problems
+        .check(
+            config_path
+                .as_deref()
+                .map(config::FileConfig::load)
+                .transpose(),
+        )
+        .flatten();
+    let (bots, tokens) = match &file_config {
+        Some(config) => (config.bots.clone(), config.tokens.clone()),
+        // The config file error is already reported
+        None if config_path.is_some() => (Vec::new(), Vec::new()),
+        None => problems.check(read_lists()).unwrap_or_default(),
+    };
+    bots_errors(&bots)
+        .into_iter()
+        .for_each(|err| problems.push(err));
+    let listen_addr = problems.check(listen_addr(
+        file_config.as_ref().and_then(|c| c.host.clone()),
+        file_config.as_ref().and_then(|c| c.port),
+    ));
+    let tls_config = problems.check(tls_config());
+    // The verified client certificates authorize the requests without a token
+    let client_cert_auth = env_flag("TELEPINGBOT_TLS_CLIENT_CERT_AUTH");
+    if client_cert_auth && client_ca().is_none() {
+        problems.push("`TELEPINGBOT_TLS_CLIENT_CERT_AUTH` requires `TELEPINGBOT_TLS_CLIENT_CA`");
+    }
+    let access_log = problems.check(accesslog::AccessLog::from_env());
+    let server_headers = problems.check(api::ServerHeaders::new(
+        env_flag("TELEPINGBOT_HIDE_POWERED_BY"),
+        file_config
+            .as_ref()
+            .map(|c| c.headers.clone())
+            .unwrap_or_default()
+            .into_iter()
+            .chain(
+                env::var("TELEPINGBOT_HEADERS")
+                    .map_or_else(|_| Vec::new(), |headers| parse_headers(&headers)),
+            )
+            .collect(),
+    ));
+    let cors = problems.check(cors::Cors::from_env());
+    let ip_allowlist = problems.check(ipallow::IpAllowlist::from_env());
+    let webhook = problems.check(
+        env::var("TELEPINGBOT_WEBHOOK_URL")
+            .ok()
+            .filter(|url| !url.is_empty())
+            .map(|url| webhook::Webhook::from_url(&url))
+            .transpose(),
+    );
+    let mock = env_flag("TELEPINGBOT_MOCK");
+    let sessions_dir = env::var("TELEPINGBOT_SESSIONS_DIR")
+        .ok()
+        .filter(|dir| !dir.is_empty());
+    if let Some(dir) = sessions_dir
+        .as_deref()
+        .filter(|dir| !mock && !Path::new(dir).is_dir())
+    {
+        problems.push(format!("The sessions directory `{dir}` does not exist"));
+    }
+    let api_credentials = match &file_config {
+        _ if mock => None,
+        Some(config) => Some((config.api_hash.clone(), config.api_id)),
+        None => {
+            let api_hash = env::var("TELEPINGBOT_API_HASH").ok();
+            if api_hash.is_none() {
+                problems.push("`TELEPINGBOT_API_HASH` environment variable is required");
+            }
+            let api_id = problems.number("TELEPINGBOT_API_ID");
+            if env::var("TELEPINGBOT_API_ID").is_err() {
+                problems.push("`TELEPINGBOT_API_ID` environment variable is required");
+            }
+            api_hash.zip(api_id)
+        }
+    };
+    let login_config = superbot::LoginConfig::from_env(&mut problems);
+
+    let response_timeout = file_config
+        .as_ref()
+        .and_then(|c| c.response_timeout)
+        .or_else(|| problems.positive("TELEPINGBOT_RESPONSE_TIMEOUT"))
+        .unwrap_or(DEFAULT_RESPONSE_TIMEOUT);
+    let ping_timeout = problems
+        .positive("TELEPINGBOT_PING_TIMEOUT")
+        .unwrap_or(response_timeout + DEFAULT_PING_TIMEOUT_MARGIN);
+    let rate_limit = problems.positive("TELEPINGBOT_RATE_LIMIT");
+    let sweep_interval = problems
+        .positive("TELEPINGBOT_SWEEP_INTERVAL")
+        .unwrap_or(DEFAULT_SWEEP_INTERVAL);
+    let status_save_interval = problems
+        .positive("TELEPINGBOT_STATUS_SAVE_INTERVAL")
+        .unwrap_or(DEFAULT_STATUS_SAVE_INTERVAL);
+    // The monitor is enabled with the interval or the webhook
+    let monitor_interval = problems
+        .positive("TELEPINGBOT_MONITOR_INTERVAL")
+        .or_else(|| matches!(webhook, Some(Some(_))).then_some(DEFAULT_MONITOR_INTERVAL));
+    let monitor_jitter = problems
+        .number("TELEPINGBOT_MONITOR_JITTER")
+        .unwrap_or_default();
+    if matches!(monitor_interval, Some(interval) if monitor_jitter >= interval) {
+        problems.push("`TELEPINGBOT_MONITOR_JITTER` must be less than the monitor interval");
+    }
+    let ping_attempts = problems.positive("TELEPINGBOT_PING_ATTEMPTS").unwrap_or(1);
+    let retry_delay = problems
+        .number("TELEPINGBOT_RETRY_DELAY")
+        .unwrap_or(DEFAULT_RETRY_DELAY);
+    let startup_grace = problems.number("TELEPINGBOT_STARTUP_GRACE").unwrap_or(0);
+    let max_concurrency = problems.positive("TELEPINGBOT_MAX_CONCURRENCY");
+    let max_queue = problems.number("TELEPINGBOT_MAX_QUEUE");
+    let batch_max_bots = problems
+        .positive("TELEPINGBOT_BATCH_MAX_BOTS")
+        .unwrap_or(DEFAULT_BATCH_MAX_BOTS);
+    let batch_max_body = problems
+        .positive("TELEPINGBOT_BATCH_MAX_BODY")
+        .unwrap_or(DEFAULT_BATCH_MAX_BODY);
+    let unusable_after = problems
+        .positive("TELEPINGBOT_UNUSABLE_AFTER")
+        .unwrap_or(DEFAULT_UNUSABLE_AFTER);
+    let exit_on_revoked = env_flag("TELEPINGBOT_EXIT_ON_REVOKED");
+    let strict_http = env_flag("TELEPINGBOT_STRICT_HTTP");
+    let cooldown = problems.number("TELEPINGBOT_COOLDOWN").unwrap_or_default();
+    let seen_within = problems
+        .number("TELEPINGBOT_SEEN_WITHIN")
+        .unwrap_or_default();
+    let passive_window = problems
+        .positive("TELEPINGBOT_PASSIVE_WINDOW")
+        .unwrap_or(DEFAULT_PASSIVE_WINDOW);
+    #[cfg(feature = "history")]
+    let history = problems
+        .check(
+            env::var("TELEPINGBOT_DB_PATH")
+                .ok()
+                .map(|path| history::History::open(&path))
+                .transpose(),
+        )
+        .flatten();
+    #[cfg(not(feature = "history"))]
+    if env::var("TELEPINGBOT_DB_PATH").is_ok() {
+        problems
+            .push("The ping history is not supported, build the API with the `history` feature");
+    }
+
+    match (
+        listen_addr,
+        tls_config,
+        access_log,
+        server_headers,
+        cors,
+        ip_allowlist,
+        webhook,
+    ) {
+        (
+            Some(listen_addr),
+            Some(tls_config),
+            Some(access_log),
+            Some(server_headers),
+            Some(cors),
+            Some(ip_allowlist),
+            Some(webhook),
+        ) if problems.is_empty() => Ok(StartupConfig {
+            bots,
+            tokens,
+            tokens_files: tokens_files(file_config.is_some()),
+            listen_addr,
+            tls_config,
+            client_cert_auth,
+            access_log,
+            server_headers,
+            cors,
+            ip_allowlist,
+            webhook,
+            sessions_dir,
+            api_credentials,
+            login_config,
+            response_timeout,
+            ping_timeout,
+            rate_limit,
+            sweep_interval,
+            status_save_interval,
+            monitor_interval,
+            monitor_jitter,
+            ping_attempts,
+            retry_delay,
+            startup_grace,
+            max_concurrency,
+            max_queue,
+            batch_max_bots,
+            batch_max_body,
+            unusable_after,
+            exit_on_revoked,
+            strict_http,
+            cooldown,
+            seen_within,
+            passive_window,
+            #[cfg(feature = "history")]
+            history,
+        }),
+        // Each invalid value records its problem
+        _ => Err(problems),
+    }
+}
+
+/// Run the API until the shutdown, the config is read from the environment and the command
+/// line arguments
+pub async fn run() -> Result<()> {
+    // The logger reads `RUST_LOG`, so the `.env` file is loaded first
+    dotenv::dotenv().ok();
+    logger::init();
+    log::info!("Starting the API");
+
+    // Validate the config, the telegram session and the bots, then exit without serving
+    let check = env::args().any(|arg| arg == "--check");
+    let StartupConfig {
+        bots,
+        tokens,
+        tokens_files,
+        listen_addr,
+        tls_config,
+        client_cert_auth,
+        access_log,
+        server_headers,
+        cors,
+        ip_allowlist,
+        webhook,
+        sessions_dir,
+        api_credentials,
+        login_config,
+        response_timeout,
+        ping_timeout,
+        rate_limit,
+        sweep_interval,
+        status_save_interval,
+        monitor_interval,
+        monitor_jitter,
+        ping_attempts,
+        retry_delay,
+        startup_grace,
+        max_concurrency,
+        max_queue,
+        batch_max_bots,
+        batch_max_body,
+        unusable_after,
+        exit_on_revoked,
+        strict_http,
+        cooldown,
+        seen_within,
+        passive_window,
+        #[cfg(feature = "history")]
+        history,
+    } = startup_config().unwrap_or_else(|problems| problems.exit(check));
+    let webhook = webhook.map(Arc::new);
+
+    // The telegram accounts with the api hash and id to reconnect, `None` in the mock mode
+    let (accounts, sign_out) = match api_credentials {
+        None => {
+            log::warn!("Running in the mock mode, no telegram connection is made");
+            (None, false)
+        }
+        Some((api_hash, api_id)) => {
+            let session_file = env_path("TELEPINGBOT_SESSION_FILE", SESSION_FILE);
+            // Multiple accounts from the sessions directory, or one account from the session file
+            let (clients, sign_out) = match &sessions_dir {
+                Some(dir) => (
+                    superbot::login_dir(dir, api_hash.clone(), api_id, &login_config).await?,
+                    false,
+                ),
+                None => {
+                    let (client, sign_out) = superbot::login(
+                        &session_file,
+                        api_hash.clone(),
+                        api_id,
+                        &login_config,
+                        !check,
+                    )
+                    .await?;
+                    (vec![client], sign_out)
+                }
+            };
+            (
+                Some((
+                    Arc::new(
+                        pinger::Accounts::new(clients)
+                            .delete_probes(env_flag("TELEPINGBOT_DELETE_PROBES")),
+                    ),
+                    api_hash,
+                    api_id,
+                )),
+                sign_out,
+            )
+        }
+    };
+    let pinger: Arc<dyn pinger::BotPinger> = match &accounts {
+        Some((accounts, ..)) => Arc::clone(accounts) as _,
+        None => Arc::new(pinger::MockPinger::new(
+            env::var("TELEPINGBOT_MOCK_DOWN_BOTS")
+                .map(|bots| {
+                    bots.split(',')
+                        .filter(|b| !b.trim().is_empty())
+                        .map(api::clean_username)
+                        .collect()
+                })
+                .unwrap_or_default(),
+        )),
+    };
+    let app_state = api::AppState::new(
+        bots,
+        tokens,
+        pinger,
+        response_timeout,
+        ping_timeout,
+        Arc::new(metrics::Metrics::default()),
+        rate_limit,
+    )
+    .strict_http(strict_http)
+    .retries(ping_attempts, Duration::from_secs(retry_delay))
+    .max_concurrency(max_concurrency)
+    .max_queue(max_queue)
+    .ip_allowlist(ip_allowlist)
+    .batch_limits(batch_max_bots, batch_max_body)
+    .require_https(env_flag("TELEPINGBOT_REQUIRE_HTTPS"))
+    .error_detail(env_flag("TELEPINGBOT_ERROR_DETAIL"))
+    .followup_command(env::var("TELEPINGBOT_FOLLOWUP_COMMAND").ok())
+    .cooldown(cooldown)
+    .banner_hint(env::var("TELEPINGBOT_BANNER_HINT").ok())
+    .client_cert_auth(client_cert_auth)
+    .passive(env_flag("TELEPINGBOT_PASSIVE"), passive_window)
+    .seen_within(seen_within)
+    .exit_on_revoked(exit_on_revoked)
+    .unusable_after(unusable_after)
+    .admin_token(env::var("TELEPINGBOT_ADMIN_TOKEN").ok())
+    .tokens_files(tokens_files);
+    #[cfg(feature = "history")]
+    let app_state = app_state.history(history);
+    let app_state = Arc::new(app_state);
+    let status_file = env::var("TELEPINGBOT_STATUS_FILE")
+        .ok()
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from);
+    if let Some(status_file) = &status_file {
+        app_state.load_status(status_file);
+    }
+
+    let bots: Vec<String> = app_state
+        .access
+        .read()
+        .unwrap()
+        .bots
+        .iter()
+        .map(|b| b.username.clone())
+        .collect();
+    let clients = accounts
+        .as_ref()
+        .map(|(accounts, ..)| accounts.clients())
+        .unwrap_or_default();
+    let mut resolve_errors = Vec::new();
+    for (account, client) in clients.iter().enumerate() {
+        for err in superbot::pre_resolve(client, account, &bots).await {
+            if !resolve_errors.contains(&err) {
+                resolve_errors.push(err);
+            }
+        }
+    }
+    resolve_errors.iter().for_each(|e| log::error!("{e}"));
+    if check {
+        let authorized = app_state.pinger.is_authorized().await;
+        let passed = print_check_report(&app_state, clients.len(), authorized, &resolve_errors);
+        if sign_out {
+            clients[0].sign_out_disconnect().await?;
+        }
+        std::process::exit(if passed { 0 } else { 1 });
+    }
+    if !resolve_errors.is_empty() && env::args().any(|arg| arg == "--strict") {
+        if sign_out {
+            clients[0].sign_out_disconnect().await?;
+        }
+        return Err(Error::Config(
+            "Some bots can't be resolved, aborting because of `--strict`".to_owned(),
+        ));
+    }
+
+    #[cfg(unix)]
+    {
+        let reload_state = Arc::clone(&app_state);
+        tokio::spawn(async move { reload_on_hangup(reload_state).await });
+        tokio::spawn(cycle_level_on_usr1());
+    }
+
+    let shutdown = app_state.shutdown.clone();
+    let ctrl_c_shutdown = shutdown.clone();
+    tokio::spawn(async move {
+        if let Err(err) = tokio::signal::ctrl_c().await {
+            log::error!("Faild to listen to ctrl_c event: {err}");
+            return;
+        }
+        log::info!("Received Ctrl-C, shutting down");
+        ctrl_c_shutdown.cancel();
+    });
+
+    tokio::spawn(sweep_outdead(
+        Duration::from_secs(sweep_interval),
+        Arc::clone(&app_state),
+        shutdown.clone(),
+    ));
+    if let Some(status_file) = &status_file {
+        tokio::spawn(save_status_every(
+            status_file.clone(),
+            Duration::from_secs(status_save_interval),
+            Arc::clone(&app_state),
+            shutdown.clone(),
+        ));
+    }
+    if let Some(monitor_interval) = monitor_interval {
+        log::info!(
+            "Monitoring the bots every {monitor_interval} seconds, with {monitor_jitter} \
+                 seconds jitter"
+        );
+        tokio::spawn(superbot::monitor(
+            Arc::clone(&app_state),
+            webhook,
+            Duration::from_secs(monitor_interval),
+            Duration::from_secs(monitor_jitter),
+            shutdown.clone(),
+        ));
+    }
+
+    let listener = salvo::conn::TcpListener::new(listen_addr);
+    let bind_error = |err| Error::Config(format!("Faild to listen on `{listen_addr}`: {err}"));
+    let base_path = env::var("TELEPINGBOT_BASE_PATH")
+        .map(|path| path.trim_matches('/').to_owned())
+        .unwrap_or_default();
+    let service = api::service(
+        Arc::clone(&app_state),
+        cors,
+        api::BasePath(base_path),
+        access_log,
+        server_headers,
+    );
+    let server_handler = match tls_config {
+        #[cfg(feature = "tls")]
+        Some(config) => {
+            let acceptor = listener
+                .rustls(config)
+                .try_bind()
+                .await
+                .map_err(bind_error)?;
+            log::info!("Listening on `{listen_addr}` with TLS");
+            tokio::spawn(serve(acceptor, service, shutdown.clone()))
+        }
+        #[cfg(not(feature = "tls"))]
+        Some(never) => match never {},
+        None => {
+            let acceptor = listener.try_bind().await.map_err(bind_error)?;
+            tokio::spawn(serve(acceptor, service, shutdown.clone()))
+        }
+    };
+    let warm_up_state = Arc::clone(&app_state);
+    let handlers = clients.len();
+    tokio::spawn(async move {
+        warm_up_state
+            .warm_up(Duration::from_secs(startup_grace), handlers)
+            .await
+    });
+    let client_handlers = accounts
+        .iter()
+        .flat_map(|(accounts, api_hash, api_id)| {
+            (0..clients.len()).map(|account| {
+                tokio::spawn(superbot::handler(
+                    Arc::clone(&app_state),
+                    Arc::clone(accounts),
+                    account,
+                    api_hash.clone(),
+                    *api_id,
+                    login_config.clone(),
+                    shutdown.clone(),
+                ))
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let (client_results, server_result) = tokio::join!(
+        futures_util::future::join_all(client_handlers),
+        server_handler
+    );
+    client_results
+        .into_iter()
+        .collect::<std::result::Result<(), _>>()?;
+    server_result?;
+
+    // The last results since the last save
+    if let Some(status_file) = &status_file {
+        match app_state.save_status(status_file) {
+            Ok(()) => log::info!("Saved the bots status to the status file"),
+            Err(err) => log::error!("Faild to save the status file: {err}"),
+        }
+    }
+
+    let sign_out = sign_out || app_state.sign_out.load(Ordering::Relaxed);
+    log::debug!("Close the API, telegram sign out status: {sign_out}");
+    if let (true, Some((accounts, ..))) = (sign_out, &accounts) {
+        // The client may be reconnected, only the session file account can sign out
+        let client = accounts.client(0);
+        match tokio::time::timeout(
+            Duration::from_secs(SHUTDOWN_TIMEOUT),
+            client.sign_out_disconnect(),
+        )
+        .await
+        {
+            Ok(result) => result?,
+            Err(_) => log::error!("Timeout while signing out from telegram"),
+        }
+        log::info!("Signed out from telegram");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_list_skips_comments_and_blank_lines() {
+        let content = "# The bots\n\n@first_bot\n   \n# @commented_bot\n@second_bot # inline\n";
+        assert_eq!(parse_list(content), ["@first_bot", "@second_bot"]);
+    }
+
+    #[test]
+    fn parse_list_handles_crlf_and_trailing_whitespace() {
+        let content = "@first_bot  \r\n\t@second_bot\t\r\n\r\n# comment\r\n";
+        assert_eq!(parse_list(content), ["@first_bot", "@second_bot"]);
+    }
+
+    #[test]
+    fn parse_list_keeps_hash_inside_entry() {
+        // Only `#` after a whitespace starts an inline comment, e.g. in a token
+        assert_eq!(parse_list("tok#en # comment"), ["tok#en"]);
+    }
+
+    #[test]
+    fn new_res_ignores_the_unrelated_updates() {
+        let pinged: Mutex<HashMap<u64, PingedBot>> = Mutex::new(HashMap::new());
+        pinged.add_new(1, None);
+        let now = chrono::Utc::now().timestamp();
+
+        // A message from a bot that is not pinged doesn't add it
+        pinged.new_res(2, now, "hello");
+        assert!(pinged.lock().unwrap().get(&2).is_none());
+        // An old queued message is not a response to the new ping
+        pinged.new_res(1, now - 60, "hello");
+        assert_eq!(pinged.check(1, 0), PingStatus::NoResponse);
+
+        pinged.new_res(1, now, "hello");
+        assert_eq!(pinged.check(1, 0), PingStatus::Alive);
+    }
+}
@@ -0,0 +1,224 @@
+// A simple API to ping telegram bots and returns if it's online or not.
+// Copyright (C) 2023  Awiteb <awitb@hotmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::{
+    cell::RefCell,
+    future::Future,
+    io::{self, Write},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use serde_json::{Map, Value};
+
+tokio::task_local! {
+    /// The id of the current request
+    static REQUEST_ID: String;
+}
+
+/// The log level set at runtime as `LevelFilter as usize`, `0` if it's not set
+static LEVEL_OVERRIDE: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    /// The extra fields of the current log record, used by the JSON format
+    static FIELDS: RefCell<Map<String, Value>> = RefCell::new(Map::new());
+}
+
+/// The log output format
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LogFormat {
+    /// Human readable logs, the default
+    Pretty,
+    /// One JSON object per line
+    Json,
+}
+
+impl LogFormat {
+    /// Returns the log format from `TELEPINGBOT_LOG_FORMAT` environment variable
+    fn from_env() -> Self {
+        match std::env::var("TELEPINGBOT_LOG_FORMAT") {
+            Ok(format) if format.eq_ignore_ascii_case("json") => Self::Json,
+            Ok(format) if !format.is_empty() && !format.eq_ignore_ascii_case("pretty") => {
+                eprintln!("Unknown `TELEPINGBOT_LOG_FORMAT` value `{format}`, using `pretty`");
+                Self::Pretty
+            }
+            _ => Self::Pretty,
+        }
+    }
+}
+
+/// Returns the minimum log level from `TELEPINGBOT_LOG_LEVEL` environment variable, default
+/// is `info`
+fn level_from_env() -> log::LevelFilter {
+    match std::env::var("TELEPINGBOT_LOG_LEVEL") {
+        Ok(level) if !level.is_empty() => level.parse().unwrap_or_else(|_| {
+            eprintln!("Unknown `TELEPINGBOT_LOG_LEVEL` value `{level}`, using `info`");
+            log::LevelFilter::Info
+        }),
+        _ => log::LevelFilter::Info,
+    }
+}
+
+/// The logger, adds the request id to the pretty logs and its level can be changed at runtime
+struct Logger {
+    /// The formatter, accepts all the levels, the records are filtered before it
+    inner: env_logger::Logger,
+    /// The filter from `TELEPINGBOT_LOG_LEVEL` and `RUST_LOG`
+    filter: env_logger::filter::Filter,
+    format: LogFormat,
+}
+
+impl log::Log for Logger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        match level_override() {
+            Some(level) => metadata.level() <= level,
+            None => self.filter.enabled(metadata),
+        }
+    }
+
+    fn log(&self, record: &log::Record) {
+        let enabled = match level_override() {
+            Some(level) => record.level() <= level,
+            None => self.filter.matches(record),
+        };
+        if !enabled {
+            return;
+        }
+        match request_id() {
+            Some(request_id) if self.format == LogFormat::Pretty => self.inner.log(
+                &log::Record::builder()
+                    .args(format_args!("[{request_id}] {}", record.args()))
+                    .metadata(record.metadata().clone())
+                    .module_path(record.module_path())
+                    .file(record.file())
+                    .line(record.line())
+                    .build(),
+            ),
+            _ => self.inner.log(record),
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush()
+    }
+}
+
+/// Returns the log level set at runtime, `None` if the level is from the environment
+fn level_override() -> Option<log::LevelFilter> {
+    match LEVEL_OVERRIDE.load(Ordering::Relaxed) {
+        0 => None,
+        level => log::LevelFilter::iter().nth(level),
+    }
+}
+
+/// Cycle the log level, `info` to `debug` to `trace` and back to `info`, the other levels go
+/// to `debug`. Returns the new level, it overrides the `RUST_LOG` filters
+pub(crate) fn cycle_level() -> log::LevelFilter {
+    let level = match log::max_level() {
+        log::LevelFilter::Debug => log::LevelFilter::Trace,
+        log::LevelFilter::Trace => log::LevelFilter::Info,
+        _ => log::LevelFilter::Debug,
+    };
+    LEVEL_OVERRIDE.store(level as usize, Ordering::Relaxed);
+    log::set_max_level(level);
+    level
+}
+
+/// Format the log record as a JSON object
+fn json_format(buf: &mut env_logger::fmt::Formatter, record: &log::Record) -> io::Result<()> {
+    let mut object = Map::new();
+    object.insert(
+        "timestamp".to_owned(),
+        chrono::Utc::now().to_rfc3339().into(),
+    );
+    object.insert("level".to_owned(), record.level().as_str().into());
+    object.insert("target".to_owned(), record.target().into());
+    object.insert("message".to_owned(), record.args().to_string().into());
+    if let Some(request_id) = request_id() {
+        object.insert("request_id".to_owned(), request_id.into());
+    }
+    FIELDS.with(|fields| {
+        object.extend(
+            fields
+                .borrow()
+                .iter()
+                .map(|(key, value)| (key.clone(), value.clone())),
+        )
+    });
+    writeln!(buf, "{}", Value::Object(object))
+}
+
+/// Initialize the logger, the log level is from `TELEPINGBOT_LOG_LEVEL` environment variable,
+/// and `RUST_LOG` overrides it when set
+pub(crate) fn init() {
+    let format = LogFormat::from_env();
+    let mut builder = match format {
+        LogFormat::Pretty => pretty_env_logger::formatted_builder(),
+        LogFormat::Json => {
+            let mut builder = env_logger::Builder::new();
+            builder.format(json_format);
+            builder
+        }
+    };
+    // The records are filtered by the logger, so the level can be changed at runtime
+    builder.filter_level(log::LevelFilter::Trace);
+    let mut filter = env_logger::filter::Builder::new();
+    filter.filter_level(level_from_env());
+    if let Ok(filters) = std::env::var("RUST_LOG") {
+        filter.parse(&filters);
+    }
+    let logger = Logger {
+        inner: builder.build(),
+        filter: filter.build(),
+        format,
+    };
+
+    log::set_max_level(logger.filter.filter());
+    log::set_boxed_logger(Box::new(logger)).expect("The logger is initialized once")
+}
+
+/// Returns the request id of the current request, if any
+fn request_id() -> Option<String> {
+    REQUEST_ID.try_with(Clone::clone).ok()
+}
+
+/// Run the request future with its request id, all its logs will have the id
+pub(crate) async fn with_request_id<F: Future>(request_id: String, future: F) -> F::Output {
+    REQUEST_ID.scope(request_id, future).await
+}
+
+/// Log with the request id, for the code outside the request future (e.g. the catchers)
+pub(crate) fn sync_with_request_id<R>(request_id: String, log: impl FnOnce() -> R) -> R {
+    REQUEST_ID.sync_scope(request_id, log)
+}
+
+/// Log with extra fields, the fields only appear in the JSON format.
+/// The fields must be logged in the same thread, so don't await inside `log`
+pub(crate) fn with_fields<R>(fields: &[(&str, Value)], log: impl FnOnce() -> R) -> R {
+    FIELDS.with(|current| {
+        let mut current = current.borrow_mut();
+        for (key, value) in fields {
+            current.insert((*key).to_owned(), value.clone());
+        }
+    });
+    let result = log();
+    FIELDS.with(|current| {
+        let mut current = current.borrow_mut();
+        for (key, _) in fields {
+            current.remove(*key);
+        }
+    });
+    result
+}
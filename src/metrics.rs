@@ -0,0 +1,189 @@
+// A simple API to ping telegram bots and returns if it's online or not.
+// Copyright (C) 2023  Awiteb <awitb@hotmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::{collections::BTreeMap, fmt::Write, sync::Mutex};
+
+/// The latency histogram buckets, in seconds
+const LATENCY_BUCKETS: [f64; 8] = [0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0];
+
+/// The result of a ping, used as the `result` label
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum PingResult {
+    Alive,
+    NoResponse,
+    Error,
+}
+
+#[derive(Debug, Default)]
+struct MetricsInner {
+    /// Pings count by bot and result
+    pings: BTreeMap<(String, PingResult), u64>,
+    /// The last ping status of each bot
+    bots_up: BTreeMap<String, bool>,
+    /// Non-cumulative count of each latency bucket, the last one is `+Inf`
+    latency_buckets: [u64; LATENCY_BUCKETS.len() + 1],
+    /// The sum of the latencies, in seconds
+    latency_sum: f64,
+    /// The number of the received messages
+    updates: u64,
+    /// The number of the flood waits
+    flood_waits: u64,
+    /// The sum of the flood waits, in seconds
+    flood_wait_seconds: u64,
+}
+
+/// Prometheus metrics of the API
+#[derive(Debug, Default)]
+pub(crate) struct Metrics {
+    inner: Mutex<MetricsInner>,
+}
+
+impl PingResult {
+    pub(crate) fn as_str(&self) -> &'static str {
+        match self {
+            Self::Alive => "alive",
+            Self::NoResponse => "no_response",
+            Self::Error => "error",
+        }
+    }
+}
+
+impl Metrics {
+    /// Record a ping result of the bot, with the response latency if the bot response
+    pub(crate) fn record_ping(&self, bot: &str, result: PingResult, latency_ms: Option<u64>) {
+        let mut inner = self.inner.lock().unwrap();
+        *inner.pings.entry((bot.to_owned(), result)).or_default() += 1;
+        inner
+            .bots_up
+            .insert(bot.to_owned(), result == PingResult::Alive);
+        if let Some(latency_ms) = latency_ms {
+            let latency = latency_ms as f64 / 1000.0;
+            let bucket = LATENCY_BUCKETS
+                .iter()
+                .position(|&le| latency <= le)
+                .unwrap_or(LATENCY_BUCKETS.len());
+            inner.latency_buckets[bucket] += 1;
+            inner.latency_sum += latency;
+        }
+    }
+
+    /// Record a new received message
+    pub(crate) fn record_update(&self) {
+        self.inner.lock().unwrap().updates += 1;
+    }
+
+    /// Record a flood wait of the given seconds, returns the flood waits count and their
+    /// seconds in total
+    pub(crate) fn record_flood_wait(&self, seconds: u32) -> (u64, u64) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.flood_waits += 1;
+        inner.flood_wait_seconds += u64::from(seconds);
+        (inner.flood_waits, inner.flood_wait_seconds)
+    }
+
+    /// Render the metrics in the Prometheus text format
+    pub(crate) fn render(&self) -> String {
+        let inner = self.inner.lock().unwrap();
+        let mut out = String::new();
+
+        writeln!(
+            out,
+            "# HELP telepingbot_pings_total Total number of the pings"
+        )
+        .ok();
+        writeln!(out, "# TYPE telepingbot_pings_total counter").ok();
+        for ((bot, result), count) in &inner.pings {
+            writeln!(
+                out,
+                "telepingbot_pings_total{{bot=\"{bot}\",result=\"{}\"}} {count}",
+                result.as_str()
+            )
+            .ok();
+        }
+
+        writeln!(
+            out,
+            "# HELP telepingbot_bot_up Whether the bot response to the last ping"
+        )
+        .ok();
+        writeln!(out, "# TYPE telepingbot_bot_up gauge").ok();
+        for (bot, up) in &inner.bots_up {
+            writeln!(out, "telepingbot_bot_up{{bot=\"{bot}\"}} {}", u8::from(*up)).ok();
+        }
+
+        writeln!(
+            out,
+            "# HELP telepingbot_response_latency_seconds The bot response latency"
+        )
+        .ok();
+        writeln!(out, "# TYPE telepingbot_response_latency_seconds histogram").ok();
+        let mut cumulative = 0;
+        for (bucket, count) in inner.latency_buckets.iter().enumerate() {
+            cumulative += count;
+            let le = LATENCY_BUCKETS
+                .get(bucket)
+                .map(f64::to_string)
+                .unwrap_or_else(|| "+Inf".to_owned());
+            writeln!(
+                out,
+                "telepingbot_response_latency_seconds_bucket{{le=\"{le}\"}} {cumulative}"
+            )
+            .ok();
+        }
+        writeln!(
+            out,
+            "telepingbot_response_latency_seconds_sum {}",
+            inner.latency_sum
+        )
+        .ok();
+        writeln!(
+            out,
+            "telepingbot_response_latency_seconds_count {cumulative}"
+        )
+        .ok();
+
+        writeln!(
+            out,
+            "# HELP telepingbot_updates_total Total number of the received messages"
+        )
+        .ok();
+        writeln!(out, "# TYPE telepingbot_updates_total counter").ok();
+        writeln!(out, "telepingbot_updates_total {}", inner.updates).ok();
+
+        writeln!(
+            out,
+            "# HELP telepingbot_flood_waits_total Total number of the telegram flood waits"
+        )
+        .ok();
+        writeln!(out, "# TYPE telepingbot_flood_waits_total counter").ok();
+        writeln!(out, "telepingbot_flood_waits_total {}", inner.flood_waits).ok();
+
+        writeln!(
+            out,
+            "# HELP telepingbot_flood_wait_seconds_total Total seconds of the telegram flood waits"
+        )
+        .ok();
+        writeln!(out, "# TYPE telepingbot_flood_wait_seconds_total counter").ok();
+        writeln!(
+            out,
+            "telepingbot_flood_wait_seconds_total {}",
+            inner.flood_wait_seconds
+        )
+        .ok();
+
+        out
+    }
+}
@@ -0,0 +1,707 @@
+// A simple API to ping telegram bots and returns if it's online or not.
+// Copyright (C) 2023  Awiteb <awitb@hotmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use serde_json::{json, Value};
+
+/// The Swagger UI page, it loads the UI from the CDN
+pub(crate) const SWAGGER_UI: &str = r##"<!DOCTYPE html>
+<html lang="en">
+<head>
+  <meta charset="utf-8" />
+  <title>TelePingBot API</title>
+  <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css" />
+</head>
+<body>
+  <div id="swagger-ui"></div>
+  <script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js"></script>
+  <script>
+    window.onload = () => {
+      window.ui = SwaggerUIBundle({ url: "openapi.json", dom_id: "#swagger-ui" });
+    };
+  </script>
+</body>
+</html>
+"##;
+
+/// Returns the `$ref` of the component schema
+fn schema_ref(name: &str) -> Value {
+    json!({ "$ref": format!("#/components/schemas/{name}") })
+}
+
+/// Returns a JSON response of the component schema
+fn json_response(description: &str, schema: Value) -> Value {
+    json!({
+        "description": description,
+        "content": { "application/json": { "schema": schema } }
+    })
+}
+
+/// Returns the OpenAPI 3 spec of the API, the base path is the routes prefix without the slashes
+pub(crate) fn spec(base_path: &str) -> Value {
+    let message = || schema_ref("Message");
+    let error = |description: &str| json_response(description, message());
+    let cached_param = json!({
+        "name": "cached",
+        "in": "query",
+        "required": false,
+        "description": "Returns the last monitor result instead of pinging the bot",
+        "schema": { "type": "boolean", "default": false }
+    });
+
+    let format_param = json!({
+        "name": "format",
+        "in": "query",
+        "required": false,
+        "description": "The response body format, `compat` for the simplified `{\"ok\": bool, \"detail\": string}`",
+        "schema": { "type": "string", "enum": ["default", "compat"], "default": "default" }
+    });
+
+    let bot_username_param = json!({
+        "name": "bot_username",
+        "in": "path",
+        "required": true,
+        "description": "The bot username without `@`",
+        "schema": { "type": "string" }
+    });
+
+    let ping_responses = json!({
+        "200": json_response(
+            "The bot is online, or offline with `alive: false` if \
+             `TELEPINGBOT_STRICT_HTTP` is enabled",
+            message()
+        ),
+        "400": error("The bot is not authorized, or the bot username is invalid"),
+        "403": error(
+            "The token is not allowed to ping the bot, or telegram blocked \
+             or restricted the superbot from sending to it"
+        ),
+        "404": error("The bot is offline"),
+        "429": error("Too many requests, see `Retry-After` header"),
+        "500": error("Internal server error"),
+        "502": error("Telegram request error"),
+        "503": error("The telegram session is revoked, the API is warming up, or too many pings are waiting"),
+        "504": error("Timeout while pinging the bot")
+    });
+
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "TelePingBot",
+            "description": env!("CARGO_PKG_DESCRIPTION"),
+            "version": env!("CARGO_PKG_VERSION"),
+            "license": { "name": env!("CARGO_PKG_LICENSE") }
+        },
+        "servers": [{ "url": format!("/{base_path}") }],
+        "paths": {
+            "/ping/@{bot_username}": {
+                "get": {
+                    "summary": "Ping the bot and returns if it's online or not",
+                    "security": [{ "token": [] }],
+                    "parameters": [bot_username_param, cached_param, format_param],
+                    "responses": ping_responses.clone()
+                },
+                "head": {
+                    "summary": "Ping the bot and returns the status code only, without a body",
+                    "security": [{ "token": [] }],
+                    "parameters": [bot_username_param, cached_param, format_param],
+                    "responses": ping_responses.clone()
+                }
+            },
+            "/ping/{bot_username}": {
+                "get": {
+                    "summary": "Same as `/ping/@{bot_username}`, without the `@`",
+                    "security": [{ "token": [] }],
+                    "parameters": [bot_username_param, cached_param, format_param],
+                    "responses": ping_responses.clone()
+                },
+                "head": {
+                    "summary": "Same as `HEAD /ping/@{bot_username}`, without the `@`",
+                    "security": [{ "token": [] }],
+                    "parameters": [bot_username_param, cached_param, format_param],
+                    "responses": ping_responses.clone()
+                }
+            },
+            "/ping/id/{telegram_id}": {
+                "get": {
+                    "summary": "Ping the bot by its telegram id",
+                    "security": [{ "token": [] }],
+                    "parameters": [
+                        {
+                            "name": "telegram_id",
+                            "in": "path",
+                            "required": true,
+                            "description": "The bot telegram id",
+                            "schema": { "type": "integer", "format": "int64" }
+                        },
+                        cached_param,
+                        format_param
+                    ],
+                    "responses": ping_responses
+                }
+            },
+            "/ping": {
+                "post": {
+                    "summary": "Ping multiple bots at once",
+                    "security": [{ "token": [] }],
+                    "parameters": [cached_param],
+                    "requestBody": {
+                        "required": true,
+                        "content": { "application/json": { "schema": schema_ref("BatchPing") } }
+                    },
+                    "responses": {
+                        "200": json_response(
+                            "The results of the bots",
+                            json!({ "type": "array", "items": schema_ref("BatchResult") })
+                        ),
+                        "207": json_response(
+                            "The results of the bots, some bots are not authorized",
+                            json!({ "type": "array", "items": schema_ref("BatchResult") })
+                        ),
+                        "400": json_response(
+                            "Invalid body, too many bots, or none of the bots is in the bots list",
+                            json!({ "oneOf": [
+                                message(),
+                                { "type": "array", "items": schema_ref("BatchResult") }
+                            ] })
+                        ),
+                        "403": json_response(
+                            "Unauthorized token, or all the bots are out of the token scope",
+                            json!({ "oneOf": [
+                                message(),
+                                { "type": "array", "items": schema_ref("BatchResult") }
+                            ] })
+                        ),
+                        "413": error("The body is too large"),
+                        "429": error("Too many requests, see `Retry-After` header")
+                    }
+                }
+            },
+            "/bots": {
+                "get": {
+                    "summary": "The bots that the token can ping",
+                    "security": [{ "token": [] }],
+                    "responses": {
+                        "200": json_response(
+                            "The bots with their last monitor result",
+                            json!({ "type": "array", "items": schema_ref("Bot") })
+                        ),
+                        "403": error("Unauthorized token")
+                    }
+                }
+            },
+            "/health": {
+                "get": {
+                    "summary": "The API health check",
+                    "parameters": [{
+                        "name": "ready",
+                        "in": "query",
+                        "required": false,
+                        "description": "Check the readiness too, the API is not ready while warming up",
+                        "schema": { "type": "boolean" }
+                    }],
+                    "responses": {
+                        "200": json_response("The API is healthy", schema_ref("Health")),
+                        "503": json_response(
+                            "Telegram is not authorized, or the session is revoked, or the API is \
+                             not ready with `?ready=true`",
+                            schema_ref("Health")
+                        )
+                    }
+                }
+            },
+            "/": {
+                "get": {
+                    "summary": "The API banner, with a hint to use it",
+                    "responses": {
+                        "200": json_response("The API banner", schema_ref("Banner"))
+                    }
+                }
+            },
+            "/version": {
+                "get": {
+                    "summary": "The API version and build info",
+                    "responses": {
+                        "200": json_response("The version of the API", schema_ref("Version"))
+                    }
+                }
+            },
+            "/history/@{bot_username}": {
+                "get": {
+                    "summary": "The ping history of the bot, only with `TELEPINGBOT_DB_PATH`",
+                    "security": [{ "token": [] }],
+                    "parameters": [
+                        {
+                            "name": "bot_username",
+                            "in": "path",
+                            "required": true,
+                            "description": "The bot username without `@`",
+                            "schema": { "type": "string" }
+                        },
+                        {
+                            "name": "since",
+                            "in": "query",
+                            "required": false,
+                            "description": "Only the results since the time",
+                            "schema": { "type": "string", "format": "date-time" }
+                        }
+                    ],
+                    "responses": {
+                        "200": json_response(
+                            "The newest 1000 results, oldest first",
+                            json!({ "type": "array", "items": schema_ref("HistoryEntry") })
+                        ),
+                        "400": error("The bot isn't authorized, or invalid `since`"),
+                        "403": error("Unauthorized token, or the bot isn't in the token scope"),
+                        "429": error("Too many requests"),
+                        "500": error("Can't read the history database")
+                    }
+                }
+            },
+            "/uptime/@{bot_username}": {
+                "get": {
+                    "summary": "The uptime of the bot over a time window, only with `TELEPINGBOT_DB_PATH`",
+                    "security": [{ "token": [] }],
+                    "parameters": [
+                        {
+                            "name": "bot_username",
+                            "in": "path",
+                            "required": true,
+                            "description": "The bot username without `@`",
+                            "schema": { "type": "string" }
+                        },
+                        {
+                            "name": "window",
+                            "in": "query",
+                            "required": false,
+                            "description": "The time window, e.g. `1h`, `24h` or `7d`",
+                            "schema": { "type": "string", "default": "24h" }
+                        }
+                    ],
+                    "responses": {
+                        "200": json_response("The uptime of the bot", schema_ref("Uptime")),
+                        "400": error("The bot isn't authorized, or invalid `window`"),
+                        "403": error("Unauthorized token, or the bot isn't in the token scope"),
+                        "429": error("Too many requests"),
+                        "500": error("Can't read the history database")
+                    }
+                }
+            },
+            "/debug/pings": {
+                "get": {
+                    "summary": "The current pings, only with `TELEPINGBOT_ADMIN_TOKEN`",
+                    "security": [{ "token": [] }],
+                    "responses": {
+                        "200": json_response(
+                            "The current pings",
+                            json!({ "type": "array", "items": schema_ref("PingedBot") })
+                        ),
+                        "403": error("Unauthorized admin token")
+                    }
+                }
+            },
+            "/tokens": {
+                "post": {
+                    "summary": "Add an API token, only with `TELEPINGBOT_ADMIN_TOKEN`",
+                    "security": [{ "token": [] }],
+                    "requestBody": {
+                        "required": true,
+                        "content": { "application/json": { "schema": schema_ref("NewToken") } }
+                    },
+                    "responses": {
+                        "201": json_response("The added token", schema_ref("Token")),
+                        "400": error("Invalid body, or the tokens can't be persisted"),
+                        "403": error("Unauthorized admin token"),
+                        "409": error("The token already exists")
+                    }
+                }
+            },
+            "/tokens/{id}": {
+                "delete": {
+                    "summary": "Revoke an API token, only with `TELEPINGBOT_ADMIN_TOKEN`",
+                    "security": [{ "token": [] }],
+                    "parameters": [
+                        {
+                            "name": "id",
+                            "in": "path",
+                            "required": true,
+                            "description": "The token id, its sha256 digest",
+                            "schema": { "type": "string" }
+                        },
+                        {
+                            "name": "persist",
+                            "in": "query",
+                            "required": false,
+                            "description": "Remove the token from the tokens file too",
+                            "schema": { "type": "boolean", "default": false }
+                        }
+                    ],
+                    "responses": {
+                        "200": error("The token is revoked"),
+                        "400": error("The tokens can't be persisted"),
+                        "403": error("Unauthorized admin token"),
+                        "404": error("There is no token with this id")
+                    }
+                }
+            },
+            "/shutdown": {
+                "post": {
+                    "summary": "Shut down the API gracefully, only with `TELEPINGBOT_ADMIN_TOKEN`",
+                    "security": [{ "token": [] }],
+                    "parameters": [{
+                        "name": "sign_out",
+                        "in": "query",
+                        "required": false,
+                        "description": "Sign out from telegram after the shutdown",
+                        "schema": { "type": "boolean", "default": false }
+                    }],
+                    "responses": {
+                        "202": error("The API is shutting down"),
+                        "403": error("Unauthorized admin token")
+                    }
+                }
+            },
+            "/ws": {
+                "get": {
+                    "summary": "Stream the bots status changes, detected by the monitor, over WebSocket",
+                    "security": [{ "token": [] }],
+                    "parameters": [{
+                        "name": "token",
+                        "in": "query",
+                        "required": false,
+                        "description": "The token, if it's not in the `Authorization` header",
+                        "schema": { "type": "string" }
+                    }],
+                    "responses": {
+                        "101": { "description": "Switching to WebSocket, each message is a `StatusEvent`" },
+                        "403": error("Unauthorized token"),
+                        "429": error("Too many requests")
+                    }
+                }
+            },
+            "/metrics": {
+                "get": {
+                    "summary": "Prometheus metrics",
+                    "responses": {
+                        "200": {
+                            "description": "The metrics in the Prometheus text format",
+                            "content": { "text/plain": { "schema": { "type": "string" } } }
+                        }
+                    }
+                }
+            }
+        },
+        "components": {
+            "securitySchemes": {
+                "token": { "type": "apiKey", "in": "header", "name": "Authorization" }
+            },
+            "schemas": {
+                "HistoryEntry": {
+                    "type": "object",
+                    "required": ["alive", "latency_ms", "checked_at"],
+                    "properties": {
+                        "alive": { "type": "boolean" },
+                        "latency_ms": { "type": "integer", "nullable": true },
+                        "checked_at": { "type": "string", "format": "date-time" }
+                    }
+                },
+                "Uptime": {
+                    "type": "object",
+                    "required": ["bot", "window", "uptime", "samples"],
+                    "properties": {
+                        "bot": { "type": "string", "example": "@SomeBot" },
+                        "window": { "type": "string", "example": "24h" },
+                        "uptime": {
+                            "type": "number",
+                            "nullable": true,
+                            "description": "The fraction of the alive pings, `null` if there is no pings"
+                        },
+                        "samples": { "type": "integer" }
+                    }
+                },
+                "StatusEvent": {
+                    "type": "object",
+                    "required": ["bot", "alive", "latency_ms", "ts"],
+                    "properties": {
+                        "bot": { "type": "string", "example": "@SomeBot" },
+                        "alive": { "type": "boolean" },
+                        "latency_ms": { "type": "integer", "nullable": true },
+                        "ts": { "type": "string", "format": "date-time" }
+                    }
+                },
+                "Message": {
+                    "type": "object",
+                    "required": ["message", "status", "alive", "latency_ms"],
+                    "properties": {
+                        "message": { "type": "string" },
+                        "status": {
+                            "type": "boolean",
+                            "description": "Whether the request is successful, not whether \
+                                            the bot is alive"
+                        },
+                        "latency_ms": {
+                            "type": "integer",
+                            "nullable": true,
+                            "description": "The bot response latency in milliseconds"
+                        },
+                        "alive": {
+                            "type": "boolean",
+                            "nullable": true,
+                            "description": "Whether the bot is alive, `null` if the bot is \
+                                            not pinged, e.g. an error"
+                        },
+                        "retry_after_seconds": {
+                            "type": "integer",
+                            "description": "The seconds to wait before retrying"
+                        },
+                        "error_code": {
+                            "type": "string",
+                            "enum": [
+                                "AUTH_MISSING_HEADER",
+                                "AUTH_INVALID_VALUE",
+                                "AUTH_FORBIDDEN",
+                                "RATE_LIMITED",
+                                "TELEGRAM_BLOCKED",
+                                "TELEGRAM_RESTRICTED",
+                                "TELEGRAM_UNAUTHORIZED",
+                                "WARMING_UP",
+                                "HTTPS_REQUIRED",
+                                "IP_FORBIDDEN",
+                                "BATCH_TOO_MANY_BOTS",
+                                "BATCH_TOO_LARGE",
+                                "OVERLOADED",
+                                "COOLDOWN"
+                            ]
+                        },
+                        "checked_at": {
+                            "type": "string",
+                            "format": "date-time",
+                            "description": "When the bot is pinged, only in the cached results"
+                        },
+                        "telegram_id": {
+                            "type": "integer",
+                            "description": "The bot telegram id, only when the bot is pinged and alive"
+                        },
+                        "error_detail": {
+                            "type": "string",
+                            "description": "The ping error summary, only with `TELEPINGBOT_ERROR_DETAIL`",
+                            "example": "USERNAME_NOT_OCCUPIED"
+                        }
+                    }
+                },
+                "BatchPing": {
+                    "type": "object",
+                    "required": ["bots"],
+                    "additionalProperties": false,
+                    "properties": {
+                        "bots": { "type": "array", "minItems": 1, "items": { "type": "string" } }
+                    }
+                },
+                "BatchResult": {
+                    "allOf": [
+                        {
+                            "type": "object",
+                            "required": ["bot", "authorized", "status_code"],
+                            "properties": {
+                                "bot": { "type": "string" },
+                                "authorized": { "type": "boolean" },
+                                "status_code": { "type": "integer" }
+                            }
+                        },
+                        message()
+                    ]
+                },
+                "Bot": {
+                    "type": "object",
+                    "required": ["username"],
+                    "properties": {
+                        "username": { "type": "string" },
+                        "alive": { "type": "boolean" },
+                        "checked_at": { "type": "string", "format": "date-time" },
+                        "last_seen": { "type": "string", "format": "date-time" }
+                    }
+                },
+                "Health": {
+                    "type": "object",
+                    "required": ["status", "telegram_authorized", "degraded", "ready", "usable"],
+                    "properties": {
+                        "status": { "type": "boolean" },
+                        "telegram_authorized": { "type": "boolean" },
+                        "degraded": {
+                            "type": "boolean",
+                            "description": "Whether the telegram session is revoked while running"
+                        },
+                        "ready": {
+                            "type": "boolean",
+                            "description": "Whether the startup grace period is over"
+                        },
+                        "usable": {
+                            "type": "boolean",
+                            "description": "Whether the last pings are not all failed with account errors, e.g. the account is banned"
+                        }
+                    }
+                },
+                "NewToken": {
+                    "type": "object",
+                    "required": ["token"],
+                    "additionalProperties": false,
+                    "properties": {
+                        "token": { "type": "string" },
+                        "bots": {
+                            "type": "array",
+                            "items": { "type": "string" },
+                            "description": "The bots the token is allowed to ping, all the bots if it's empty"
+                        },
+                        "persist": {
+                            "type": "boolean",
+                            "default": false,
+                            "description": "Append the token to the tokens file"
+                        }
+                    }
+                },
+                "Token": {
+                    "type": "object",
+                    "required": ["id", "bots"],
+                    "properties": {
+                        "id": { "type": "string", "description": "The token sha256 digest" },
+                        "bots": {
+                            "type": ["array", "null"],
+                            "items": { "type": "string" },
+                            "description": "The bots the token is allowed to ping, `null` for all the bots"
+                        }
+                    }
+                },
+                "PingedBot": {
+                    "type": "object",
+                    "required": ["telegram_id", "ping_in", "is_response", "age_ms", "status"],
+                    "properties": {
+                        "telegram_id": { "type": "integer", "format": "int64" },
+                        "ping_in": { "type": "string", "format": "date-time" },
+                        "is_response": { "type": "boolean" },
+                        "age_ms": {
+                            "type": "integer",
+                            "description": "The milliseconds since the bot is pinged"
+                        },
+                        "status": {
+                            "type": "string",
+                            "enum": ["alive", "no_response", "pending"],
+                            "description": "The ping is pending until the response timeout elapses"
+                        }
+                    }
+                },
+                "Banner": {
+                    "type": "object",
+                    "required": ["name", "version", "hint"],
+                    "properties": {
+                        "name": { "type": "string" },
+                        "version": { "type": "string" },
+                        "hint": { "type": "string", "description": "How to use the API" }
+                    }
+                },
+                "Version": {
+                    "type": "object",
+                    "required": ["version", "git_sha", "build_time"],
+                    "properties": {
+                        "version": { "type": "string" },
+                        "git_sha": {
+                            "type": "string",
+                            "description": "The git commit of the build, or `unknown`"
+                        },
+                        "build_time": { "type": "string", "format": "date-time" }
+                    }
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use salvo::Router;
+
+    use crate::{
+        accesslog::AccessLog,
+        api::{self, AppState, BasePath, ServerHeaders},
+        metrics::Metrics,
+        pinger::MockPinger,
+    };
+
+    /// Collect the routes of the router with their methods, the path parameters are in the
+    /// OpenAPI format, e.g. `/ping/@{bot_username}`
+    fn routes(router: &Router, prefix: &str, routes: &mut Vec<(String, String)>) {
+        let mut path = prefix.to_owned();
+        let mut method = None;
+        for filter in router.filters() {
+            let filter = format!("{filter:?}");
+            if let Some(segment) = filter.strip_prefix("path:") {
+                path = format!(
+                    "{}/{}",
+                    path.trim_end_matches('/'),
+                    segment.trim_matches('/')
+                );
+            } else if let Some(name) = filter.strip_prefix("method:") {
+                method = Some(name.to_lowercase());
+            }
+        }
+        if let Some(method) = method {
+            let path = if path.is_empty() { "/" } else { path.as_str() };
+            routes.push((path.replace('<', "{").replace('>', "}"), method));
+        }
+        for child in router.routers() {
+            self::routes(child, &path, routes);
+        }
+    }
+
+    #[test]
+    fn every_route_is_in_the_spec() {
+        let app_state = AppState::new(
+            vec!["@SomeTestBot".to_owned()],
+            vec!["token".to_owned()],
+            Arc::new(MockPinger::new(Vec::new())),
+            1,
+            2,
+            Arc::new(Metrics::default()),
+            None,
+        )
+        .admin_token(Some("admin".to_owned()));
+        #[cfg(feature = "history")]
+        let app_state = app_state.history(Some(crate::history::History::open(":memory:").unwrap()));
+        let service = api::service(
+            Arc::new(app_state),
+            None,
+            BasePath(String::new()),
+            AccessLog::from_env().unwrap(),
+            ServerHeaders::new(false, Vec::new()).unwrap(),
+        );
+
+        let spec = super::spec("");
+        let mut registered = Vec::new();
+        routes(&service.router, "", &mut registered);
+        assert!(!registered.is_empty());
+        for (path, method) in registered {
+            // The docs routes are not part of the API
+            if ["/openapi.json", "/docs"].contains(&path.as_str()) {
+                continue;
+            }
+            assert!(
+                spec["paths"][&path][&method].is_object(),
+                "`{} {path}` is not in the OpenAPI spec",
+                method.to_uppercase()
+            );
+        }
+    }
+}
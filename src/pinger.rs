@@ -0,0 +1,357 @@
+// A simple API to ping telegram bots and returns if it's online or not.
+// Copyright (C) 2023  Awiteb <awitb@hotmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::{
+    fmt::Debug,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::{Duration, Instant},
+};
+
+use grammers_client::Client;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    api::{BotConfig, Probe},
+    metrics::Metrics,
+    superbot::{handle_updates, login, LoginConfig},
+    validate::Problems,
+    PingList, Pings,
+};
+
+/// The first telegram id of the mock bots, far from the real ids
+const MOCK_FIRST_ID: u64 = 1 << 52;
+
+/// The outcome of a successful ping
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PingOutcome {
+    /// The bot response
+    Alive {
+        /// The response latency in milliseconds
+        latency_ms: u64,
+        /// The bot telegram id
+        telegram_id: u64,
+    },
+    /// The bot doesn't response in the response timeout
+    NoResponse,
+}
+
+/// Pings the bots, the real telegram accounts or a mock
+#[async_trait::async_trait]
+pub(crate) trait BotPinger: Debug + Send + Sync {
+    /// Send the probe to the bot and wait its response. The passive probe waits nothing, the
+    /// bot is alive if it sent a message in the last `response_timeout` seconds
+    async fn ping(&self, bot: &BotConfig, response_timeout: u64) -> crate::Result<PingOutcome>;
+    /// Resolve the bot username, returns the bot telegram id
+    async fn resolve(&self, bot_username: &str) -> crate::Result<u64>;
+    /// Returns `true` if all the accounts are authorized
+    async fn is_authorized(&self) -> bool;
+    /// Returns the pings of the pinger
+    fn pings(&self) -> &Pings;
+}
+
+/// The real telegram accounts
+#[derive(Debug)]
+pub(crate) struct Accounts {
+    /// The telegram clients of the accounts, a client is replaced when it's reconnected
+    clients: RwLock<Vec<Client>>,
+    /// The next account to ping with, the accounts are used in round-robin
+    next_account: AtomicUsize,
+    /// Delete the sent command after the bot response or the response timeout
+    delete_probes: bool,
+    /// The pings of the accounts, shared with their updates handlers
+    pings: Pings,
+}
+
+/// A pinger of one telegram account, to embed the pinger in another service. Its telegram
+/// updates are handled in the background until it's dropped
+#[derive(Debug)]
+pub struct Pinger {
+    accounts: Arc<Accounts>,
+    /// The time to wait the bot response, in seconds
+    response_timeout: u64,
+    /// Cancelled when the pinger is dropped, to stop its updates handler
+    shutdown: CancellationToken,
+}
+
+/// A mock pinger, the bots response immediately without connecting to telegram
+#[derive(Debug)]
+pub(crate) struct MockPinger {
+    /// The clean usernames of the bots that never response
+    down_bots: Vec<String>,
+    /// The pinged bots, the mock telegram id of a bot is its position
+    ids: Mutex<Vec<String>>,
+    pings: Pings,
+}
+
+impl PingOutcome {
+    /// Returns the response latency in milliseconds, `None` if the bot doesn't response
+    pub fn latency_ms(self) -> Option<u64> {
+        match self {
+            Self::Alive { latency_ms, .. } => Some(latency_ms),
+            Self::NoResponse => None,
+        }
+    }
+}
+
+impl Accounts {
+    pub(crate) fn new(clients: Vec<Client>) -> Self {
+        Self {
+            clients: RwLock::new(clients),
+            next_account: AtomicUsize::new(0),
+            delete_probes: false,
+            pings: Pings::default(),
+        }
+    }
+
+    /// Delete the sent commands after the bot response or the response timeout, to keep the
+    /// chats clean
+    pub(crate) fn delete_probes(mut self, delete_probes: bool) -> Self {
+        self.delete_probes = delete_probes;
+        self
+    }
+
+    /// Returns the current telegram client of the account
+    pub(crate) fn client(&self, account: usize) -> Client {
+        self.clients.read().unwrap()[account].clone()
+    }
+
+    /// Returns the current telegram clients of all the accounts
+    pub(crate) fn clients(&self) -> Vec<Client> {
+        self.clients.read().unwrap().clone()
+    }
+
+    /// Replace the telegram client of the account with the reconnected one
+    pub(crate) fn set_client(&self, account: usize, client: Client) {
+        self.clients.write().unwrap()[account] = client;
+    }
+
+    /// Returns the next account to ping with and its client
+    fn next_client(&self) -> (usize, Client) {
+        let clients = self.clients.read().unwrap();
+        let account = self.next_account.fetch_add(1, Ordering::Relaxed) % clients.len();
+        (account, clients[account].clone())
+    }
+}
+
+#[async_trait::async_trait]
+impl BotPinger for Accounts {
+    async fn ping(&self, bot: &BotConfig, response_timeout: u64) -> crate::Result<PingOutcome> {
+        let (account, client) = self.next_client();
+        match &bot.probe {
+            Probe::Command(command) => {
+                let (telegram_id, probe) = crate::superbot::send_start(
+                    &client,
+                    account,
+                    &bot.username,
+                    bot.chat,
+                    &bot.command(command),
+                    bot.expect.clone(),
+                    &self.pings,
+                )
+                .await?;
+                let outcome = wait_for_outcome(&self.pings, telegram_id, response_timeout).await;
+                // Deleted in the background, so it doesn't delay the ping result
+                if self.delete_probes {
+                    let bot_username = bot.username.clone();
+                    tokio::spawn(async move {
+                        crate::superbot::delete_probe(&probe, &bot_username).await
+                    });
+                }
+                Ok(outcome)
+            }
+            Probe::Inline(query) => {
+                let started_in = Instant::now();
+                let answered = tokio::time::timeout(
+                    Duration::from_secs(response_timeout),
+                    crate::superbot::send_inline_query(&client, account, &bot.username, query),
+                )
+                .await;
+                match answered {
+                    Ok(Ok(Some(telegram_id))) => Ok(PingOutcome::Alive {
+                        latency_ms: started_in.elapsed().as_millis() as u64,
+                        telegram_id,
+                    }),
+                    Ok(Ok(None)) | Err(_) => Ok(PingOutcome::NoResponse),
+                    Ok(Err(err)) => Err(err),
+                }
+            }
+            Probe::Passive => Ok(passive_outcome(
+                &self.pings,
+                self.resolve(&bot.username).await?,
+                response_timeout,
+            )),
+        }
+    }
+
+    async fn resolve(&self, bot_username: &str) -> crate::Result<u64> {
+        let (account, client) = self.next_client();
+        crate::superbot::resolve_bot(&client, account, bot_username)
+            .await
+            .map(|chat| chat.id as u64)
+    }
+
+    async fn is_authorized(&self) -> bool {
+        let clients = self.clients();
+        futures_util::future::join_all(clients.iter().map(|c| c.is_authorized()))
+            .await
+            .iter()
+            .all(|r| matches!(r, Ok(true)))
+    }
+
+    fn pings(&self) -> &Pings {
+        &self.pings
+    }
+}
+
+impl Pinger {
+    /// Connect to telegram with the session file, the session must be signed in before, e.g.
+    /// by running the API. The default response timeout is 2 seconds, see
+    /// [`Self::response_timeout`]
+    pub async fn connect(session_file: &str, api_hash: String, api_id: i32) -> crate::Result<Self> {
+        let mut problems = Problems::default();
+        let login_config = LoginConfig::from_env(&mut problems);
+        problems.into_result()?;
+        let (client, _) =
+            login(session_file, api_hash.clone(), api_id, &login_config, false).await?;
+        let accounts = Arc::new(Accounts::new(vec![client]));
+        let shutdown = CancellationToken::new();
+        tokio::spawn(handle_updates(
+            Arc::clone(&accounts),
+            0,
+            Arc::new(Metrics::default()),
+            |reason| log::error!("The telegram session is revoked ({reason}), sign in again"),
+            (api_hash, api_id, login_config),
+            shutdown.clone(),
+        ));
+        Ok(Self {
+            accounts,
+            response_timeout: crate::DEFAULT_RESPONSE_TIMEOUT,
+            shutdown,
+        })
+    }
+
+    /// Set the time to wait the bot response, in seconds
+    pub fn response_timeout(mut self, response_timeout: u64) -> Self {
+        self.response_timeout = response_timeout;
+        self
+    }
+
+    /// Send `/start` to the bot and wait its response, the username can be with or without `@`
+    pub async fn ping(&self, bot_username: &str) -> crate::Result<PingOutcome> {
+        let bot = BotConfig::parse(bot_username);
+        self.accounts.ping(&bot, self.response_timeout).await
+    }
+}
+
+impl Drop for Pinger {
+    fn drop(&mut self) {
+        self.shutdown.cancel();
+    }
+}
+
+impl MockPinger {
+    /// Create a mock pinger, the `down_bots` are clean usernames of the bots that never response
+    pub(crate) fn new(down_bots: Vec<String>) -> Self {
+        Self {
+            down_bots,
+            ids: Mutex::new(Vec::new()),
+            pings: Pings::default(),
+        }
+    }
+
+    /// Returns the mock telegram id of the bot
+    fn telegram_id(&self, bot_username: &str) -> u64 {
+        let mut ids = self.ids.lock().unwrap();
+        let position = ids
+            .iter()
+            .position(|b| b == bot_username)
+            .unwrap_or_else(|| {
+                ids.push(bot_username.to_owned());
+                ids.len() - 1
+            });
+        MOCK_FIRST_ID + position as u64
+    }
+}
+
+#[async_trait::async_trait]
+impl BotPinger for MockPinger {
+    async fn ping(&self, bot: &BotConfig, response_timeout: u64) -> crate::Result<PingOutcome> {
+        let telegram_id = self.telegram_id(&bot.username);
+        // The mock bots are always sending messages
+        if bot.probe == Probe::Passive {
+            if !self.down_bots.contains(&bot.username) {
+                self.pings.seen(telegram_id, chrono::Utc::now().timestamp());
+            }
+            return Ok(passive_outcome(&self.pings, telegram_id, response_timeout));
+        }
+        log::debug!("Mock sending `{:?}` to `@{}`", bot.probe, bot.username);
+        self.pings.pinged.add_new(telegram_id, bot.expect.clone());
+        // The mock bots echo the probe
+        if !self.down_bots.contains(&bot.username) {
+            self.pings.seen(telegram_id, chrono::Utc::now().timestamp());
+            let text = match &bot.probe {
+                Probe::Command(command) => bot.command(command),
+                Probe::Inline(query) => query.into(),
+                Probe::Passive => "".into(),
+            };
+            self.pings
+                .pinged
+                .new_res(telegram_id, chrono::Utc::now().timestamp(), &text);
+        }
+        Ok(wait_for_outcome(&self.pings, telegram_id, response_timeout).await)
+    }
+
+    async fn resolve(&self, bot_username: &str) -> crate::Result<u64> {
+        Ok(self.telegram_id(bot_username))
+    }
+
+    async fn is_authorized(&self) -> bool {
+        true
+    }
+
+    fn pings(&self) -> &Pings {
+        &self.pings
+    }
+}
+
+/// Returns the outcome of the passive ping, the bot is alive if it sent a message in the last
+/// `window` seconds. There is no response latency, so it's zero
+pub(crate) fn passive_outcome(pings: &Pings, telegram_id: u64, window: u64) -> PingOutcome {
+    let last_seen = pings.last_seen.lock().unwrap().get(&telegram_id).copied();
+    log::debug!("The bot {telegram_id} is last seen in {last_seen:?}");
+    match last_seen {
+        Some(seen) if chrono::Utc::now().timestamp() - seen < window as i64 => PingOutcome::Alive {
+            latency_ms: 0,
+            telegram_id,
+        },
+        _ => PingOutcome::NoResponse,
+    }
+}
+
+/// Wait the response of the pinged bot
+async fn wait_for_outcome(pings: &Pings, telegram_id: u64, response_timeout: u64) -> PingOutcome {
+    pings
+        .pinged
+        .wait_for_response(telegram_id, response_timeout)
+        .await
+        .map_or(PingOutcome::NoResponse, |latency_ms| PingOutcome::Alive {
+            latency_ms,
+            telegram_id,
+        })
+}
@@ -0,0 +1,83 @@
+// A simple API to ping telegram bots and returns if it's online or not.
+// Copyright (C) 2023  Awiteb <awitb@hotmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::{collections::HashMap, sync::Mutex, time::Instant};
+
+/// A token bucket
+#[derive(Debug)]
+struct Bucket {
+    /// The available requests, refilled over time
+    tokens: f64,
+    /// The last refill time
+    updated_in: Instant,
+}
+
+/// Token bucket rate limiter, keyed by the token digest
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    /// The allowed requests per minute, also the bucket capacity
+    per_minute: u32,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl RateLimiter {
+    /// Create new [`RateLimiter`] that allows `per_minute` requests per minute for each key
+    pub(crate) fn new(per_minute: u32) -> Self {
+        Self {
+            per_minute,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Take a request from the key bucket, returns the seconds to wait before retrying if
+    /// the key is over its limit
+    pub(crate) fn check(&self, key: &str) -> Result<(), u32> {
+        let capacity = f64::from(self.per_minute);
+        let per_second = capacity / 60.0;
+        let now = Instant::now();
+
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = buckets.entry(key.to_owned()).or_insert(Bucket {
+            tokens: capacity,
+            updated_in: now,
+        });
+        let elapsed = now.duration_since(bucket.updated_in).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * per_second).min(capacity);
+        bucket.updated_in = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(((1.0 - bucket.tokens) / per_second).ceil().max(1.0) as u32)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RateLimiter;
+
+    #[test]
+    fn throttles_only_the_token_over_its_budget() {
+        let limiter = RateLimiter::new(2);
+        assert_eq!(limiter.check("first"), Ok(()));
+        assert_eq!(limiter.check("first"), Ok(()));
+        // One request is refilled every 30 seconds
+        assert_eq!(limiter.check("first"), Err(30));
+        assert_eq!(limiter.check("second"), Ok(()));
+    }
+}
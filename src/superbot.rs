@@ -14,49 +14,236 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <https://www.gnu.org/licenses/>.
 
-use grammers_client::{Client, Config, InitParams, SignInError, Update};
-use grammers_session::Session;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
 
-use crate::PingList;
+use futures_util::future::join_all;
+use grammers_client::{
+    types::{Chat, Message},
+    Client, Config, InitParams, SignInError, Update,
+};
+use grammers_mtsender::InvocationError;
+use grammers_session::{PackedChat, PackedType, Session};
+use grammers_tl_types as tl;
+use is_terminal::IsTerminal;
+use lazy_static::lazy_static;
+use tokio_util::sync::CancellationToken;
 
-const SESSION_FILE: &str = "telebotping.session";
+use crate::{
+    api::{AppState, BotConfig, BotStatus, StatusEvent},
+    errors::Error,
+    metrics::Metrics,
+    pinger::{Accounts, BotPinger},
+    validate::Problems,
+    webhook::Webhook,
+    PingList, Pings,
+};
 
-pub(crate) async fn login(api_hash: String, api_id: i32) -> crate::Result<(Client, bool)> {
-    let client = Client::connect(Config {
-        session: Session::load_file_or_create(SESSION_FILE)?,
-        api_id,
-        api_hash: api_hash.clone(),
-        params: InitParams::default(),
-    })
+/// The first wait before reconnecting to telegram, doubled after each attempt, in seconds
+pub(crate) const RECONNECT_BACKOFF: u64 = 1;
+/// The max wait between the reconnect attempts, in seconds
+const RECONNECT_MAX_BACKOFF: u64 = 60;
+/// The max time of a reconnect attempt, so a half-open connection doesn't hang it, in seconds
+const RECONNECT_TIMEOUT: u64 = 30;
+/// How long a resolved username is cached, in seconds
+const RESOLVE_CACHE_TTL: u64 = 60 * 60;
+/// The default timeout of each login step, in seconds
+const DEFAULT_LOGIN_TIMEOUT: u64 = 30;
+
+lazy_static! {
+    /// The resolved usernames by the account, with the time they are resolved in.
+    /// The chats access hash is different for each account
+    static ref RESOLVED_USERNAMES: Mutex<HashMap<(usize, String), (PackedChat, Instant)>> =
+        Mutex::new(HashMap::new());
+    /// The found probe chats by the account and the configured chat id
+    static ref RESOLVED_CHATS: Mutex<HashMap<(usize, i64), PackedChat>> = Mutex::new(HashMap::new());
+}
+
+/// How to connect to telegram, parsed once from the environment variables
+#[derive(Clone)]
+pub(crate) struct LoginConfig {
+    /// The connection parameters reported to telegram
+    pub(crate) params: InitParams,
+    /// The timeout of each login network step, from `TELEPINGBOT_LOGIN_TIMEOUT`
+    pub(crate) timeout: Duration,
+}
+
+impl LoginConfig {
+    /// Parse the login config from the environment variables, the invalid values are recorded
+    /// and replaced with the defaults
+    pub(crate) fn from_env(problems: &mut Problems) -> Self {
+        Self {
+            params: init_params(problems),
+            timeout: Duration::from_secs(
+                problems
+                    .positive("TELEPINGBOT_LOGIN_TIMEOUT")
+                    .unwrap_or(DEFAULT_LOGIN_TIMEOUT),
+            ),
+        }
+    }
+}
+
+/// Run a login network step with the login timeout, the step name is used in the error
+async fn login_step<T, E>(
+    timeout: Duration,
+    step: &'static str,
+    fut: impl std::future::Future<Output = std::result::Result<T, E>>,
+) -> crate::Result<T>
+where
+    Error: From<E>,
+{
+    tokio::time::timeout(timeout, fut)
+        .await
+        .map_err(|_| Error::LoginTimeout(step))?
+        .map_err(Error::from)
+}
+
+/// Returns the telegram connection parameters, the default parameters are overridden by the
+/// environment variables. The session label is the default device model, so each instance is
+/// distinguishable in the active sessions
+fn init_params(problems: &mut Problems) -> InitParams {
+    let defaults = InitParams::default();
+    let device_model = std::env::var("TELEPINGBOT_SESSION_LABEL")
+        .ok()
+        .map(|label| label.trim().to_owned())
+        .filter(|label| !label.is_empty())
+        .map_or(defaults.device_model.clone(), |label| {
+            format!("telepingbot ({label})")
+        });
+    let string_var = |name: &str, default: String| {
+        std::env::var(name)
+            .ok()
+            .filter(|value| !value.is_empty())
+            .unwrap_or(default)
+    };
+    InitParams {
+        device_model: string_var("TELEPINGBOT_DEVICE_MODEL", device_model),
+        system_version: string_var(
+            "TELEPINGBOT_SYSTEM_VERSION",
+            defaults.system_version.clone(),
+        ),
+        app_version: string_var("TELEPINGBOT_APP_VERSION", defaults.app_version.clone()),
+        lang_code: string_var("TELEPINGBOT_LANG_CODE", defaults.lang_code.clone()),
+        // Zero to never sleep on the flood waits
+        flood_sleep_threshold: problems
+            .number::<u32>("TELEPINGBOT_FLOOD_SLEEP_THRESHOLD")
+            .map_or(defaults.flood_sleep_threshold, |threshold| {
+                (threshold > 0).then_some(threshold)
+            }),
+        update_queue_limit: problems
+            .positive("TELEPINGBOT_UPDATE_QUEUE_LIMIT")
+            .or(defaults.update_queue_limit),
+        ..defaults
+    }
+}
+
+/// Returns the value of the environment variable, or prompt for it if the stdin is interactive
+fn env_or_prompt(name: &str, message: &str) -> crate::Result<String> {
+    if let Some(value) = std::env::var(name).ok().filter(|value| !value.is_empty()) {
+        return Ok(value);
+    }
+    if !std::io::stdin().is_terminal() {
+        return Err(Error::Config(format!(
+            "`{name}` environment variable is required to sign in, the stdin is not interactive"
+        )));
+    }
+    Ok(promptly::prompt(message)?)
+}
+
+/// Login to telegram with the session file, returns the client and if it must sign out
+/// when done (the session can't be saved). Without `sign_in` the session must be signed in
+pub(crate) async fn login(
+    session_file: &str,
+    api_hash: String,
+    api_id: i32,
+    config: &LoginConfig,
+    sign_in: bool,
+) -> crate::Result<(Client, bool)> {
+    if let Some(dir) = Path::new(session_file)
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+    {
+        if !dir.is_dir() {
+            return Err(Error::Config(format!(
+                "The directory `{}` of the session file does not exist",
+                dir.display()
+            )));
+        }
+    }
+    log::info!(
+        "Connecting to telegram as the device `{}`",
+        config.params.device_model
+    );
+    let client = login_step(
+        config.timeout,
+        "connecting to telegram",
+        Client::connect(Config {
+            session: Session::load_file_or_create(session_file)
+                .map_err(|err| Error::Session(session_file.to_owned(), err))?,
+            api_id,
+            api_hash: api_hash.clone(),
+            params: config.params.clone(),
+        }),
+    )
     .await?;
     let mut sign_out = false;
 
-    if !client.is_authorized().await? {
+    let authorized = login_step(
+        config.timeout,
+        "checking the authorization",
+        client.is_authorized(),
+    )
+    .await?;
+    if !authorized && !sign_in {
+        return Err(Error::Config(format!(
+            "The session `{session_file}` is not signed in"
+        )));
+    }
+    if !authorized {
         println!("Signing in...");
-        let phone: String = promptly::prompt("Enter your phone number (international format)")?;
-        let token = client.request_login_code(&phone, api_id, &api_hash).await?;
-        let code: String = promptly::prompt("Enter the code you received")?;
-        let signed_in = client.sign_in(&token, &code).await;
+        let phone = env_or_prompt(
+            "TELEPINGBOT_PHONE",
+            "Enter your phone number (international format)",
+        )?;
+        let token = login_step(
+            config.timeout,
+            "requesting the login code",
+            client.request_login_code(&phone, api_id, &api_hash),
+        )
+        .await?;
+        let code = env_or_prompt("TELEPINGBOT_LOGIN_CODE", "Enter the code you received")?;
+        let signed_in = tokio::time::timeout(config.timeout, client.sign_in(&token, &code))
+            .await
+            .map_err(|_| Error::LoginTimeout("signing in"))?;
         match signed_in {
             Err(SignInError::PasswordRequired(password_token)) => {
                 let hint = password_token.hint().unwrap_or("None");
-                let password: String =
-                    promptly::prompt(format!("Enter the password (hint {hint})"))?;
-                client
-                    .check_password(password_token, password.trim())
-                    .await?;
+                let password = env_or_prompt(
+                    "TELEPINGBOT_2FA_PASSWORD",
+                    &format!("Enter the password (hint {hint})"),
+                )?;
+                login_step(
+                    config.timeout,
+                    "checking the password",
+                    client.check_password(password_token, password.trim()),
+                )
+                .await?;
             }
             Ok(_) => (),
-            Err(e) => panic!("{e}"),
+            Err(e) => return Err(e.into()),
         }
-        let me = client.get_me().await?;
+        let me = login_step(config.timeout, "getting the account", client.get_me()).await?;
         println!(
             "Signed in successfully to {}",
             me.username()
                 .map(|u| "@".to_owned() + u)
                 .unwrap_or_else(|| me.full_name())
         );
-        match client.session().save_to_file(SESSION_FILE) {
+        match client.session().save_to_file(session_file) {
             Ok(_) => {}
             Err(e) => {
                 println!(
@@ -71,39 +258,595 @@ pub(crate) async fn login(api_hash: String, api_id: i32) -> crate::Result<(Clien
     Ok((client, sign_out))
 }
 
-fn update_handler(upd: Update) {
-    if let Update::NewMessage(msg) = upd {
-        if let Some(sender) = msg.sender() {
-            crate::PINGED_BOTS.new_res(sender.id() as u64)
+/// Mark the sender of the new and the edited messages as alive, the edited messages are
+/// compared by their edit date. Each ping is responded once, so a message and its edit
+/// are not counted twice
+pub(crate) fn update_handler(upd: Update, metrics: &Metrics, pings: &Pings) {
+    let (msg, sent_in) = match upd {
+        Update::NewMessage(msg) => {
+            metrics.record_update();
+            let sent_in = msg.date();
+            (msg, sent_in)
+        }
+        Update::MessageEdited(msg) => {
+            let sent_in = msg.edit_date().unwrap_or_else(|| msg.date());
+            (msg, sent_in)
         }
+        _ => return,
+    };
+    if msg.outgoing() {
+        return;
+    }
+    if let Some(sender) = msg.sender() {
+        if matches!(&sender, Chat::User(user) if user.is_bot()) {
+            pings.seen(sender.id() as u64, sent_in.timestamp());
+        }
+        pings
+            .pinged
+            .new_res(sender.id() as u64, sent_in.timestamp(), msg.text())
     }
 }
 
-pub(crate) async fn handler(client: Client) {
+/// Connect to all the sessions files in the directory, one account per session.
+/// The sessions must be signed in before, e.g. with `TELEPINGBOT_SESSION_FILE`
+pub(crate) async fn login_dir(
+    dir: &str,
+    api_hash: String,
+    api_id: i32,
+    config: &LoginConfig,
+) -> crate::Result<Vec<Client>> {
+    let mut sessions: Vec<PathBuf> = std::fs::read_dir(dir)
+        .map_err(|err| {
+            Error::Config(format!(
+                "Faild to read the sessions directory `{dir}`: {err}"
+            ))
+        })?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "session"))
+        .collect();
+    sessions.sort();
+    if sessions.is_empty() {
+        return Err(Error::Config(format!(
+            "There is no `.session` files in `{dir}`"
+        )));
+    }
+
+    let mut clients = Vec::with_capacity(sessions.len());
+    for session in sessions {
+        let client = login_step(
+            config.timeout,
+            "connecting to telegram",
+            Client::connect(Config {
+                session: Session::load_file(&session)
+                    .map_err(|err| Error::Session(session.display().to_string(), err))?,
+                api_id,
+                api_hash: api_hash.clone(),
+                params: config.params.clone(),
+            }),
+        )
+        .await?;
+        if !login_step(
+            config.timeout,
+            "checking the authorization",
+            client.is_authorized(),
+        )
+        .await?
+        {
+            return Err(Error::Config(format!(
+                "The session `{}` is not signed in",
+                session.display()
+            )));
+        }
+        log::info!("Connected to the session `{}`", session.display());
+        clients.push(client);
+    }
+    Ok(clients)
+}
+
+/// The wait after each revoked session update, doubled until the updates come back
+#[derive(Debug)]
+struct RevokedBackoff(u64);
+
+impl Default for RevokedBackoff {
+    fn default() -> Self {
+        Self(RECONNECT_BACKOFF)
+    }
+}
+
+impl RevokedBackoff {
+    /// Returns the next wait, the following one is doubled up to [`RECONNECT_MAX_BACKOFF`]
+    fn next(&mut self) -> Duration {
+        let backoff = self.0;
+        self.0 = (self.0 * 2).min(RECONNECT_MAX_BACKOFF);
+        Duration::from_secs(backoff)
+    }
+
+    /// Start again from [`RECONNECT_BACKOFF`], the session is working
+    fn reset(&mut self) {
+        self.0 = RECONNECT_BACKOFF;
+    }
+
+    /// Wait the next backoff, returns `false` if the shutdown is requested while waiting
+    async fn wait(&mut self, shutdown: &CancellationToken) -> bool {
+        tokio::select! {
+            _ = shutdown.cancelled() => false,
+            _ = tokio::time::sleep(self.next()) => true,
+        }
+    }
+}
+
+/// Handle the telegram updates of the account until the shutdown, reconnect to telegram
+/// if the connection is lost
+pub(crate) async fn handler(
+    app_state: Arc<AppState>,
+    accounts: Arc<Accounts>,
+    account: usize,
+    api_hash: String,
+    api_id: i32,
+    config: LoginConfig,
+    shutdown: CancellationToken,
+) {
+    app_state.handler_started();
+    let metrics = Arc::clone(&app_state.metrics);
+    handle_updates(
+        accounts,
+        account,
+        metrics,
+        |reason| app_state.session_revoked(reason),
+        (api_hash, api_id, config),
+        shutdown,
+    )
+    .await;
+}
+
+/// Receive the updates of the account until the shutdown, the pings responses are recorded in
+/// the accounts pings. Reconnect with the api hash, id and login config if the connection is
+/// lost, and call `on_revoked` with the reason if the session is revoked
+pub(crate) async fn handle_updates(
+    accounts: Arc<Accounts>,
+    account: usize,
+    metrics: Arc<Metrics>,
+    on_revoked: impl Fn(&str),
+    (api_hash, api_id, config): (String, i32, LoginConfig),
+    shutdown: CancellationToken,
+) {
+    let mut client = accounts.client(account);
+    let mut revoked_backoff = RevokedBackoff::default();
+    loop {
+        tokio::select! {
+            _ = shutdown.cancelled() => break,
+            update = client.next_update() => match update {
+                Ok(Some(update)) => {
+                    revoked_backoff.reset();
+                    log::debug!("New update: {update:?}");
+                    let metrics = Arc::clone(&metrics);
+                    let accounts = Arc::clone(&accounts);
+                    tokio::spawn(async move { update_handler(update, &metrics, accounts.pings()) });
+                }
+                Ok(None) => revoked_backoff.reset(),
+                Err(err @ (InvocationError::Read(_) | InvocationError::Dropped)) => {
+                    log::error!("Lost the telegram connection: {err}");
+                    match reconnect(&client, &api_hash, api_id, &config, &shutdown).await {
+                        Some(new_client) => {
+                            accounts.set_client(account, new_client.clone());
+                            client = new_client;
+                        }
+                        None => break,
+                    }
+                }
+                Err(InvocationError::Rpc(rpc)) if rpc.code == 401 => {
+                    on_revoked(&rpc.name);
+                    // The session can't recover by itself, so don't flood telegram
+                    if !revoked_backoff.wait(&shutdown).await {
+                        break;
+                    }
+                }
+                Err(err) => {
+                    log::error!("Faild to get the telegram updates: {err}");
+                    tokio::select! {
+                        _ = shutdown.cancelled() => break,
+                        _ = tokio::time::sleep(Duration::from_secs(RECONNECT_BACKOFF)) => {}
+                    }
+                }
+            }
+        }
+    }
+    log::info!("Stopping the telegram updates handler");
+}
+
+/// Connect to telegram with the client session, retry with backoff until it's connected.
+/// Returns `None` if the shutdown is requested before connecting
+pub(crate) async fn reconnect(
+    client: &Client,
+    api_hash: &str,
+    api_id: i32,
+    config: &LoginConfig,
+    shutdown: &CancellationToken,
+) -> Option<Client> {
+    let session = client.session().save();
+    let mut backoff = Duration::from_secs(RECONNECT_BACKOFF);
+    let mut attempt = 1;
     loop {
+        log::info!("Reconnecting to telegram (attempt {attempt})");
+        // The updates stop until it's reconnected, so keep retrying on any failure
+        match Session::load(&session) {
+            Ok(session) => {
+                let connected = tokio::time::timeout(
+                    Duration::from_secs(RECONNECT_TIMEOUT),
+                    Client::connect(Config {
+                        session,
+                        api_id,
+                        api_hash: api_hash.to_owned(),
+                        params: config.params.clone(),
+                    }),
+                )
+                .await;
+                match connected {
+                    Ok(Ok(client)) => {
+                        log::info!("Reconnected to telegram");
+                        return Some(client);
+                    }
+                    Ok(Err(err)) => log::error!("Faild to reconnect to telegram: {err}"),
+                    Err(_) => log::error!("Timeout while reconnecting to telegram"),
+                }
+            }
+            Err(err) => log::error!("Faild to load the session: {err}"),
+        }
+
         tokio::select! {
-            _ = tokio::signal::ctrl_c() => {
+            _ = shutdown.cancelled() => return None,
+            _ = tokio::time::sleep(backoff) => {}
+        }
+        backoff = (backoff * 2).min(Duration::from_secs(RECONNECT_MAX_BACKOFF));
+        attempt += 1;
+    }
+}
+
+/// Ping the authorized bots every interval and keep the last results in the status cache,
+/// and notify the webhook when a bot goes down or up, the first ping of each bot only
+/// records its status. Each bot has its own interval, see [`BotConfig::monitor_interval`],
+/// and the due bots are pinged by their priority. The pings are delayed randomly up to the
+/// jitter, so the due bots are not pinged at the same instant
+pub(crate) async fn monitor(
+    app_state: Arc<AppState>,
+    webhook: Option<Arc<Webhook>>,
+    interval: Duration,
+    jitter: Duration,
+    shutdown: CancellationToken,
+) {
+    // When each bot should be pinged again
+    let mut next_pings: HashMap<String, Instant> = HashMap::new();
+    let mut wake_in = Instant::now();
+    loop {
+        tokio::select! {
+            _ = shutdown.cancelled() => {
+                log::info!("Stopping the bots monitor");
                 break;
             }
-            Ok(Some(update)) = client.next_update() => {
-                log::debug!("New update: {update:?}");
-                tokio::spawn(async move {
-                    update_handler(update)
-                });
+            _ = tokio::time::sleep_until(wake_in.into()) => {}
+        }
+        if !app_state.is_ready() {
+            log::debug!("The API is warming up, skipping the monitor pings");
+            wake_in = Instant::now() + interval;
+            continue;
+        }
+
+        let all_bots = app_state.access.read().unwrap().bots.clone();
+        let now = Instant::now();
+        let mut bots: Vec<BotConfig> = all_bots
+            .iter()
+            .filter(|bot| next_pings.get(&bot.username).is_none_or(|due| *due <= now))
+            .cloned()
+            .collect();
+        bots.sort_by_key(|bot| bot.priority);
+        for bot in &bots {
+            next_pings.insert(bot.username.clone(), now + bot.monitor_interval(interval));
+        }
+        // Forget the removed bots, e.g. after reloading the bots file
+        next_pings.retain(|username, _| all_bots.iter().any(|bot| &bot.username == username));
+        wake_in = next_pings.values().min().copied().unwrap_or(now + interval);
+
+        // Sorted, so the higher priority bots are still pinged first
+        let mut delays: Vec<Duration> = bots
+            .iter()
+            .map(|_| jitter.mul_f64(rand::random()))
+            .collect();
+        delays.sort();
+        let results = join_all(bots.iter().zip(delays).map(|(bot, delay)| {
+            let app_state = &app_state;
+            let shutdown = &shutdown;
+            async move {
+                tokio::select! {
+                    _ = shutdown.cancelled() => return None,
+                    _ = tokio::time::sleep(delay) => {}
+                }
+                Some(crate::api::probe_bot(app_state, bot).await)
+            }
+        }))
+        .await;
+        let mut status_cache = app_state.status_cache.write().unwrap();
+        for (bot, result) in bots.iter().zip(results) {
+            // Shutting down before the ping
+            let Some(result) = result else { continue };
+            let (alive, latency_ms) = match result {
+                Ok(outcome) => (outcome.latency_ms().is_some(), outcome.latency_ms()),
+                // We are limited, the bot status is unknown
+                Err(Error::FloodWait(_)) => continue,
+                Err(_) => (false, None),
+            };
+            let checked_at = chrono::Utc::now();
+            let last_seen = status_cache
+                .get(&bot.username)
+                .and_then(|status| status.last_seen);
+            let status = BotStatus {
+                alive,
+                latency_ms,
+                checked_at,
+                last_seen: if alive { Some(checked_at) } else { last_seen },
+            };
+            let previous = status_cache.insert(bot.username.clone(), status);
+            if matches!(previous, Some(previous) if previous.alive != alive) {
+                log::info!(
+                    "`@{}` is {}",
+                    bot.username,
+                    if alive { "up" } else { "down" }
+                );
+                // No subscribers is not an error
+                app_state
+                    .events
+                    .send(StatusEvent {
+                        bot: format!("@{}", bot.username),
+                        alive,
+                        latency_ms,
+                        ts: status.checked_at.to_rfc3339(),
+                    })
+                    .ok();
+                if let Some(webhook) = &webhook {
+                    let payload = serde_json::json!({
+                        "bot": format!("@{}", bot.username),
+                        "status": if alive { "up" } else { "down" },
+                        "alive": alive,
+                        "latency_ms": latency_ms,
+                        "timestamp": status.checked_at.to_rfc3339(),
+                    });
+                    let webhook = Arc::clone(webhook);
+                    tokio::spawn(async move { webhook.deliver(payload).await });
+                }
+            }
+        }
+        status_cache.retain(|username, _| all_bots.iter().any(|bot| &bot.username == username));
+    }
+}
+
+/// Resolve the username, from the cache if it's resolved before and not expired
+pub(crate) async fn resolve_username(
+    client: &Client,
+    account: usize,
+    username: &str,
+) -> Result<Option<PackedChat>, InvocationError> {
+    let cached = RESOLVED_USERNAMES
+        .lock()
+        .unwrap()
+        .get(&(account, username.to_owned()))
+        .filter(|(_, resolved_in)| resolved_in.elapsed() < Duration::from_secs(RESOLVE_CACHE_TTL))
+        .map(|(chat, _)| *chat);
+    if cached.is_some() {
+        log::debug!("Found `{username}` in the resolve cache");
+        return Ok(cached);
+    }
+
+    log::debug!("Resolving `{username}`");
+    match client.resolve_username(username).await {
+        Ok(Some(chat)) => {
+            let chat = chat.pack();
+            RESOLVED_USERNAMES
+                .lock()
+                .unwrap()
+                .insert((account, username.to_owned()), (chat, Instant::now()));
+            Ok(Some(chat))
+        }
+        Ok(None) => {
+            invalidate_username(account, username);
+            Ok(None)
+        }
+        Err(err) => {
+            invalidate_username(account, username);
+            Err(err)
+        }
+    }
+}
+
+/// Remove the username from the resolve cache
+fn invalidate_username(account: usize, username: &str) {
+    RESOLVED_USERNAMES
+        .lock()
+        .unwrap()
+        .remove(&(account, username.to_owned()));
+}
+
+/// Resolve all the bots, returns the errors of the bots that can't be resolved to a bot account.
+/// The bot usernames must be clean
+pub(crate) async fn pre_resolve(client: &Client, account: usize, bots: &[String]) -> Vec<String> {
+    let mut errors = Vec::new();
+    for bot_username in bots {
+        match resolve_username(client, account, bot_username).await {
+            Ok(Some(chat)) if chat.ty == PackedType::Bot => {
+                log::info!("Resolved `@{bot_username}` to `{}`", chat.id)
+            }
+            Ok(Some(_)) => {
+                invalidate_username(account, bot_username);
+                errors.push(format!("`@{bot_username}` is not a bot account"))
+            }
+            Ok(None) => errors.push(format!("`@{bot_username}` does not exist")),
+            Err(err) => errors.push(format!("Faild to resolve `@{bot_username}`: {err}")),
+        }
+    }
+    errors
+}
+
+/// Resolve the bot username, fails if the username doesn't exist or it's not a bot account
+/// (e.g. a user or a channel), so the wrong kind of chats are not pinged
+pub(crate) async fn resolve_bot(
+    client: &Client,
+    account: usize,
+    bot_username: &str,
+) -> crate::Result<PackedChat> {
+    match resolve_username(client, account, bot_username).await? {
+        Some(chat) if chat.ty == PackedType::Bot => Ok(chat),
+        Some(_) => {
+            invalidate_username(account, bot_username);
+            Err(Error::NotBot(bot_username.to_owned()))
+        }
+        None => Err(Error::Resolve(bot_username.to_owned())),
+    }
+}
+
+/// Send the command to the bot, or into the group with the bot mention if the chat is set.
+/// The ping is added to the pings, its response must match the `expect` pattern if it's set.
+/// Returns the bot telegram id and the sent message
+pub(crate) async fn send_start(
+    client: &Client,
+    account: usize,
+    bot_username: &str,
+    chat_id: Option<i64>,
+    command: &str,
+    expect: Option<regex::Regex>,
+    pings: &Pings,
+) -> crate::Result<(u64, Message)> {
+    let bot = resolve_bot(client, account, bot_username).await?;
+    let telegram_id = bot.id as u64;
+    let (chat, command) = match chat_id {
+        Some(chat_id) => (
+            resolve_chat(client, account, chat_id).await?,
+            group_command(command, bot_username),
+        ),
+        None => (bot, command.to_owned()),
+    };
+    pings.pinged.add_new(telegram_id, expect);
+    match client.send_message(chat, command).await {
+        Ok(message) => Ok((telegram_id, message)),
+        Err(err) => {
+            // The cached chat may be outdated, or we are limited (e.g. `FLOOD_WAIT`)
+            invalidate_username(account, bot_username);
+            if let Some(chat_id) = chat_id {
+                RESOLVED_CHATS.lock().unwrap().remove(&(account, chat_id));
             }
+            Err(Error::from_send_error(err))
         }
     }
 }
 
-pub(crate) async fn send_start(client: &Client, bot_username: &str) -> crate::Result<u64> {
-    if let Some(chat) = client.resolve_username(bot_username).await? {
-        let telegram_id = chat.id() as u64;
-        crate::PINGED_BOTS.add_new(telegram_id);
-        client.send_message(chat, "/start").await?;
-        // Sleep, wating the response
-        tokio::time::sleep(std::time::Duration::from_secs(2)).await;
-        Ok(telegram_id)
-    } else {
-        Err(format!("Invalid username `{bot_username}`").into())
+/// Delete the sent probe message, the failures are only logged, e.g. no permission to delete
+/// it in the group
+pub(crate) async fn delete_probe(probe: &Message, bot_username: &str) {
+    match probe.delete().await {
+        Ok(()) => log::debug!("Deleted the probe message of `@{bot_username}`"),
+        Err(err) => log::warn!("Faild to delete the probe message of `@{bot_username}`: {err}"),
+    }
+}
+
+/// Mention the bot in the command, so only it receives the command in the group,
+/// e.g. `/health arg1` to `/health@my_bot arg1`
+fn group_command(command: &str, bot_username: &str) -> String {
+    let (name, args) = command.split_once(' ').unwrap_or((command, ""));
+    if !name.starts_with('/') || name.contains('@') {
+        return command.to_owned();
+    }
+    format!("{name}@{bot_username} {args}")
+        .trim_end()
+        .to_owned()
+}
+
+/// Find the group or channel in the account dialogs, the account must be a member of it.
+/// The chat id can be the bot API id, e.g. `-1001234567890`, or the telegram id
+async fn resolve_chat(client: &Client, account: usize, chat_id: i64) -> crate::Result<PackedChat> {
+    let cached = RESOLVED_CHATS
+        .lock()
+        .unwrap()
+        .get(&(account, chat_id))
+        .copied();
+    if let Some(chat) = cached {
+        return Ok(chat);
+    }
+
+    log::debug!("Looking for the chat `{chat_id}` in the dialogs");
+    let mut dialogs = client.iter_dialogs();
+    while let Some(dialog) = dialogs.next().await? {
+        let chat = dialog.chat().pack();
+        if chat.id == chat_id || bot_api_chat_id(&chat) == chat_id {
+            RESOLVED_CHATS
+                .lock()
+                .unwrap()
+                .insert((account, chat_id), chat);
+            return Ok(chat);
+        }
+    }
+    Err(Error::ChatNotFound(chat_id))
+}
+
+/// Returns the bot API id of the chat, the groups ids are negative and the channels ids
+/// are prefixed with `-100`
+fn bot_api_chat_id(chat: &PackedChat) -> i64 {
+    match chat.ty {
+        PackedType::User | PackedType::Bot => chat.id,
+        PackedType::Chat => -chat.id,
+        PackedType::Megagroup | PackedType::Broadcast | PackedType::Gigagroup => {
+            -1_000_000_000_000 - chat.id
+        }
+    }
+}
+
+/// Send the inline query to the bot, returns the bot telegram id if the bot answers it and
+/// `None` if the bot doesn't answer in telegram's timeout
+pub(crate) async fn send_inline_query(
+    client: &Client,
+    account: usize,
+    bot_username: &str,
+    query: &str,
+) -> crate::Result<Option<u64>> {
+    let chat = resolve_bot(client, account, bot_username).await?;
+    let request = tl::functions::messages::GetInlineBotResults {
+        bot: chat.to_input_user_lossy(),
+        peer: tl::enums::InputPeer::Empty,
+        geo_point: None,
+        query: query.to_owned(),
+        offset: String::new(),
+    };
+    match client.invoke(&request).await {
+        Ok(_) => Ok(Some(chat.id as u64)),
+        Err(InvocationError::Rpc(rpc)) if rpc.is("BOT_RESPONSE_TIMEOUT") => Ok(None),
+        Err(err) => {
+            // The cached chat may be outdated, or we are limited (e.g. `FLOOD_WAIT`)
+            invalidate_username(account, bot_username);
+            Err(Error::from_send_error(err))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn revoked_backoff_doubles_until_reset() {
+        let mut backoff = RevokedBackoff::default();
+        let waits: Vec<u64> = (0..8).map(|_| backoff.next().as_secs()).collect();
+        assert_eq!(waits, [1, 2, 4, 8, 16, 32, 60, 60]);
+        backoff.reset();
+        assert_eq!(backoff.next(), Duration::from_secs(RECONNECT_BACKOFF));
+    }
+
+    #[tokio::test]
+    async fn revoked_backoff_stops_on_shutdown() {
+        let mut backoff = RevokedBackoff(RECONNECT_MAX_BACKOFF);
+        let shutdown = CancellationToken::new();
+        let cancel = shutdown.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            cancel.cancel();
+        });
+        let started = Instant::now();
+        assert!(!backoff.wait(&shutdown).await);
+        assert!(started.elapsed() < Duration::from_secs(RECONNECT_BACKOFF));
     }
 }
@@ -0,0 +1,84 @@
+// A simple API to ping telegram bots and returns if it's online or not.
+// Copyright (C) 2023  Awiteb <awitb@hotmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::{fmt::Display, str::FromStr};
+
+/// The exit code when the startup configuration is invalid
+pub(crate) const CONFIG_EXIT_CODE: i32 = 2;
+
+/// The startup configuration problems, collected so they are reported at once instead of
+/// exiting on the first one
+#[derive(Debug, Default)]
+pub(crate) struct Problems(Vec<String>);
+
+impl Problems {
+    /// Record a problem
+    pub(crate) fn push(&mut self, problem: impl Display) {
+        self.0.push(problem.to_string());
+    }
+
+    /// Returns the value if it's valid, or record its error
+    pub(crate) fn check<T, E: Display>(&mut self, result: Result<T, E>) -> Option<T> {
+        result.map_err(|err| self.push(err)).ok()
+    }
+
+    /// Parse the number environment variable, `None` if it's not set or invalid. The invalid
+    /// value is recorded
+    pub(crate) fn number<T: FromStr>(&mut self, name: &str) -> Option<T> {
+        let value = std::env::var(name).ok()?;
+        let number = value.parse().ok();
+        if number.is_none() {
+            self.push(format!("Invalid value for `{name}` must be a number"));
+        }
+        number
+    }
+
+    /// Parse the positive number environment variable, `None` if it's not set or invalid. The
+    /// invalid value is recorded
+    pub(crate) fn positive<T: FromStr + PartialOrd + Default>(&mut self, name: &str) -> Option<T> {
+        let value = std::env::var(name).ok()?;
+        let number = value.parse().ok().filter(|number| *number > T::default());
+        if number.is_none() {
+            self.push(format!(
+                "Invalid value for `{name}` must be a positive number"
+            ));
+        }
+        number
+    }
+
+    /// Returns `true` if there is no problems
+    pub(crate) fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Returns the problems as one config error, for the library where it can't exit
+    pub(crate) fn into_result(self) -> crate::Result<()> {
+        if self.0.is_empty() {
+            return Ok(());
+        }
+        Err(crate::Error::Config(self.0.join(", ")))
+    }
+
+    /// Print all the problems then exit with [`CONFIG_EXIT_CODE`]
+    pub(crate) fn exit(self, check: bool) -> ! {
+        eprintln!("The configuration has {} problems:", self.0.len());
+        self.0.iter().for_each(|problem| eprintln!("  - {problem}"));
+        if check {
+            println!("FAIL config: {} problems", self.0.len());
+        }
+        std::process::exit(CONFIG_EXIT_CODE);
+    }
+}
@@ -0,0 +1,133 @@
+// A simple API to ping telegram bots and returns if it's online or not.
+// Copyright (C) 2023  Awiteb <awitb@hotmail.com>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published
+// by the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+
+use std::time::Duration;
+
+use http_body_util::Full;
+use hyper::{
+    body::Bytes,
+    header::{CONTENT_TYPE, HOST, USER_AGENT},
+    Request, Uri,
+};
+use salvo::rt::tokio::TokioIo;
+use tokio::net::TcpStream;
+
+use crate::errors::Error;
+
+/// The time to wait the webhook response, in seconds
+const WEBHOOK_TIMEOUT: u64 = 10;
+/// The max attempts to deliver the webhook payload
+const WEBHOOK_ATTEMPTS: u32 = 5;
+/// The first backoff between the attempts, doubled after each attempt, in seconds
+const WEBHOOK_BACKOFF: u64 = 1;
+
+/// A webhook to notify with the bots status changes
+#[derive(Debug)]
+pub(crate) struct Webhook {
+    /// The host and port of the webhook, used as the `Host` header
+    authority: String,
+    host: String,
+    port: u16,
+    /// The path and query of the webhook
+    path: String,
+}
+
+impl Webhook {
+    /// Create new [`Webhook`] from the URL, only `http` URLs are supported
+    pub(crate) fn from_url(url: &str) -> crate::Result<Self> {
+        let invalid =
+            |reason: &str| Error::Config(format!("Invalid webhook URL `{url}`: {reason}"));
+        let uri: Uri = url.parse().map_err(|_| invalid("can't parse it"))?;
+        if uri.scheme_str() != Some("http") {
+            return Err(invalid("only `http` URLs are supported"));
+        }
+        let authority = uri.authority().ok_or_else(|| invalid("missing the host"))?;
+        Ok(Self {
+            authority: authority.to_string(),
+            host: authority
+                .host()
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .to_owned(),
+            port: authority.port_u16().unwrap_or(80),
+            path: uri
+                .path_and_query()
+                .map(|p| p.to_string())
+                .unwrap_or_else(|| "/".to_owned()),
+        })
+    }
+
+    /// Send the payload to the webhook once
+    async fn send(&self, payload: &str) -> crate::Result<()> {
+        let stream = TcpStream::connect((self.host.as_str(), self.port))
+            .await
+            .map_err(|err| Error::Webhook(err.to_string()))?;
+        let (mut sender, connection) = hyper::client::conn::http1::handshake(TokioIo::new(stream))
+            .await
+            .map_err(|err| Error::Webhook(err.to_string()))?;
+        tokio::spawn(async move {
+            if let Err(err) = connection.await {
+                log::debug!("The webhook connection is closed: {err}");
+            }
+        });
+
+        let request = Request::post(self.path.as_str())
+            .header(HOST, self.authority.as_str())
+            .header(CONTENT_TYPE, "application/json")
+            .header(
+                USER_AGENT,
+                concat!("telepingbot/", env!("CARGO_PKG_VERSION")),
+            )
+            .body(Full::new(Bytes::from(payload.to_owned())))
+            .map_err(|err| Error::Webhook(err.to_string()))?;
+        let response = sender
+            .send_request(request)
+            .await
+            .map_err(|err| Error::Webhook(err.to_string()))?;
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(Error::Webhook(format!(
+                "The webhook response with `{}`",
+                response.status()
+            )))
+        }
+    }
+
+    /// Deliver the payload to the webhook, retry with backoff if it's faild
+    pub(crate) async fn deliver(&self, payload: serde_json::Value) {
+        let payload = payload.to_string();
+        let mut backoff = Duration::from_secs(WEBHOOK_BACKOFF);
+        for attempt in 1..=WEBHOOK_ATTEMPTS {
+            let result =
+                tokio::time::timeout(Duration::from_secs(WEBHOOK_TIMEOUT), self.send(&payload))
+                    .await
+                    .unwrap_or_else(|_| Err(Error::Webhook("Timeout".to_owned())));
+            match result {
+                Ok(()) => {
+                    log::debug!("The webhook is delivered: {payload}");
+                    return;
+                }
+                Err(err) if attempt < WEBHOOK_ATTEMPTS => {
+                    log::warn!("Faild to deliver the webhook (attempt {attempt}): {err}");
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                }
+                Err(err) => log::error!("Faild to deliver the webhook, giving up: {err}"),
+            }
+        }
+    }
+}